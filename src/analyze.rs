@@ -0,0 +1,333 @@
+use crate::{
+    cart::Cartridge,
+    check::Diagnostic,
+    cpu::Mem,
+    decode::{op, Instruction, Program},
+};
+
+/// Sets of `Mem` variants a memory slot might hold, as bit flags.
+const NIL: u8 = 1 << 0;
+const INT: u8 = 1 << 1;
+const FLOAT: u8 = 1 << 2;
+const STR: u8 = 1 << 3;
+const ARR: u8 = 1 << 4;
+
+const NUM: u8 = INT | FLOAT;
+const ANY: u8 = NIL | INT | FLOAT | STR | ARR;
+
+/// Possible variants of every memory slot at one point in the program.
+type State = [u8; 255];
+
+/// What an instruction does to a memory slot.
+#[derive(Debug, Copy, Clone)]
+enum Effect {
+    /// Reads the slot, which has to hold one of the given variants.
+    /// `ANY` reads never fail, whatever the slot holds.
+    Read(u8, u8),
+    /// Overwrites the slot with one of the given variants.
+    Set(u8, u8),
+    /// Sets the slot only if it is still `Nil` (`0xa2`).
+    Let(u8, u8),
+    /// Writes to an array in place (`0xa3`).
+    Modify(u8),
+}
+
+/// Describes a set of variants, e.g. `an int or a float`.
+fn describe(types: u8) -> String {
+    let names = [(NIL, "nil"), (INT, "an int"), (FLOAT, "a float"), (STR, "a string"), (ARR, "a byte array")];
+
+    let names: Vec<_> = names
+        .iter()
+        .filter(|(flag, _)| types & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+
+    match names.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {last}", rest.join(", ")),
+        None => "nothing".to_string(),
+    }
+}
+
+/// The variant a constant is stored as.
+fn variant(value: &Mem) -> u8 {
+    match value {
+        Mem::Int(_) => INT,
+        Mem::Float(_) => FLOAT,
+        Mem::Str(_) => STR,
+        Mem::ByteArr(_) => ARR,
+        Mem::Nil => NIL,
+    }
+}
+
+/// Memory accesses of an instruction, in the order `Cpu::run` makes them.
+fn effects(inst: &Instruction) -> Vec<Effect> {
+    use Effect::*;
+
+    let rows = |addr: u8| (addr..addr + 8).map(|addr| Read(addr, ARR));
+
+    match *inst {
+        Instruction::Pixel { x, y, .. } => vec![Read(x, NUM), Read(y, NUM)],
+        Instruction::Sprite { rows, x, y } => rows
+            .iter()
+            .map(|addr| Read(*addr, ARR))
+            .chain([Read(x, INT), Read(y, INT)])
+            .collect(),
+        Instruction::SpriteTile { x, y, .. } => vec![Read(x, INT), Read(y, INT)],
+        Instruction::Tile { col, row, .. } => vec![Read(col, NUM), Read(row, NUM)],
+        Instruction::ScrollX(addr) | Instruction::ScrollY(addr) => vec![Read(addr, NUM)],
+        Instruction::Collide { ax, ay, bx, by, out } => {
+            vec![Read(ax, INT), Read(ay, INT), Read(bx, INT), Read(by, INT), Set(out, INT)]
+        }
+        Instruction::PixelCollide { ax, ay, a_rows, bx, by, b_rows, out } => [Read(ax, INT), Read(ay, INT)]
+            .into_iter()
+            .chain(rows(a_rows))
+            .chain([Read(bx, INT), Read(by, INT)])
+            .chain(rows(b_rows))
+            .chain([Set(out, INT)])
+            .collect(),
+        Instruction::Peek { x, y, out } => vec![Read(x, NUM), Read(y, NUM), Set(out, INT)],
+        Instruction::Tone { freq, duration, .. } => vec![Read(freq, NUM), Read(duration, NUM)],
+        Instruction::Var { ref value, addr, .. } => vec![Set(addr, variant(value))],
+        Instruction::Let { ref value, addr, .. } => vec![Let(addr, variant(value))],
+        Instruction::ArrayWrite { addr, .. } => vec![Read(addr, ARR | STR), Modify(addr)],
+        Instruction::Store { addr, .. } => vec![Read(addr, ANY)],
+        // Empty slots load as nil.
+        Instruction::Load { addr, .. } => vec![Set(addr, ANY)],
+        Instruction::Not { addr, out } => vec![Read(addr, ANY), Set(out, INT)],
+        Instruction::Gt { lhs, rhs, out } | Instruction::Lt { lhs, rhs, out } => {
+            vec![Read(lhs, ANY), Read(rhs, ANY), Set(out, INT)]
+        }
+        Instruction::Key { out, .. } | Instruction::KeyState { out, .. } => vec![Set(out, INT)],
+        Instruction::ReadChar(addr) | Instruction::MouseX(addr) | Instruction::MouseY(addr) => vec![Set(addr, INT | NIL)],
+        Instruction::ReadLine(addr) => vec![Set(addr, STR)],
+        Instruction::MouseButton { out, .. } | Instruction::Pad { out, .. } => vec![Set(out, INT)],
+        Instruction::JumpIf { cond, .. } | Instruction::JumpIfNot { cond, .. } => vec![Read(cond, ANY)],
+        Instruction::JumpVar(addr) => vec![Read(addr, INT)],
+        Instruction::Arith { code, lhs, rhs, out } => {
+            let result = if code <= 0xf3 { FLOAT } else { INT };
+
+            vec![Read(lhs, NUM), Read(rhs, NUM), Set(out, result)]
+        }
+        _ => vec![],
+    }
+}
+
+/// Runs a dataflow analysis over the program's control flow, tracking which
+/// variants each memory slot can hold at every instruction. Warns about
+/// reads of slots that might not be set yet, reads of the wrong variant and
+/// slots that are set but never read.
+///
+/// The program should have passed `check` first. Bytes that don't decode end
+/// the path they are on, since running them is an error anyway.
+pub fn analyze(cart: &Cartridge) -> Vec<Diagnostic> {
+    let mut program = Program::decode(&cart.code);
+
+    // Falling off the end, or jumping to it, finishes the program or
+    // starts it over with memory intact.
+    let end = cart.code.len();
+    let restart = cart.header.repeat && end > 0;
+
+    // Computed jumps are assumed to land on an instruction found by
+    // decoding from the start, or on the end.
+    let starts: Vec<usize> = program.offsets().chain([end]).collect();
+
+    let mut states: Vec<Option<State>> = vec![None; end + 1];
+    states[0] = Some([NIL; 255]);
+
+    let mut work = vec![0];
+
+    while let Some(pos) = work.pop() {
+        let Some(mut state) = states[pos] else {
+            continue;
+        };
+
+        let successors = match program.get(pos) {
+            Some((inst, next)) => {
+                for effect in effects(inst) {
+                    match effect {
+                        Effect::Read(..) => {}
+                        Effect::Set(addr, types) => state[addr as usize] = types,
+                        Effect::Let(addr, types) => {
+                            let slot = &mut state[addr as usize];
+
+                            if *slot & NIL != 0 {
+                                *slot = (*slot & !NIL) | types;
+                            }
+                        }
+                        Effect::Modify(addr) => state[addr as usize] &= ARR | STR,
+                    }
+                }
+
+                // Jumping past the end is the same as jumping to it.
+                match *inst {
+                    Instruction::Jump { byte } => vec![byte.min(end)],
+                    Instruction::JumpIf { byte, .. } | Instruction::JumpIfNot { byte, .. } => vec![*next, byte.min(end)],
+                    Instruction::JumpVar(_) => starts.clone(),
+                    Instruction::Invalid(_) => vec![],
+                    _ => vec![*next],
+                }
+            }
+            None if restart => vec![0],
+            None => vec![],
+        };
+
+        for next in successors {
+            let merged = match states[next] {
+                Some(old) => {
+                    let mut merged = old;
+
+                    for (slot, types) in merged.iter_mut().zip(state) {
+                        *slot |= types;
+                    }
+
+                    if merged == old {
+                        continue;
+                    }
+
+                    merged
+                }
+                None => state,
+            };
+
+            states[next] = Some(merged);
+            work.push(next);
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    let mut read = [false; 255];
+    let mut first_write: [Option<(usize, &str)>; 255] = [None; 255];
+
+    // Every instruction decoded, whether from the start or on the way.
+    let offsets: Vec<usize> = program.offsets().collect();
+
+    for pos in offsets {
+        let Some((inst, _)) = program.get(pos) else {
+            continue;
+        };
+
+        let effects = effects(inst);
+        let state = &states[pos];
+
+        // Unknown opcodes have no name, and no effects either.
+        let Some(op) = op(cart.code[pos]) else {
+            continue;
+        };
+
+        for effect in &effects {
+            match *effect {
+                Effect::Read(addr, _) | Effect::Modify(addr) => read[addr as usize] = true,
+                Effect::Set(addr, _) | Effect::Let(addr, _) => {
+                    first_write[addr as usize].get_or_insert((pos, op.name));
+                }
+            }
+        }
+
+        // Unreachable code is never run, so it can't fail.
+        let Some(state) = state else {
+            continue;
+        };
+
+        for effect in effects {
+            let Effect::Read(addr, expected) = effect else {
+                continue;
+            };
+
+            let types = state[addr as usize];
+            let name = op.name;
+
+            let message = if expected == ANY {
+                continue;
+            } else if types == NIL {
+                format!("{name}: {addr:0>2x} is read before it is ever set")
+            } else if types & !expected & !NIL != 0 {
+                format!(
+                    "{name}: expected {} at {addr:0>2x}, but it may hold {}",
+                    describe(expected),
+                    describe(types & !expected & !NIL)
+                )
+            } else if types & NIL != 0 {
+                format!("{name}: {addr:0>2x} may not be set yet")
+            } else {
+                continue;
+            };
+
+            diagnostics.push(Diagnostic { offset: pos, message });
+        }
+    }
+
+    for (addr, write) in first_write.iter().enumerate() {
+        if let (Some((offset, name)), false) = (write, read[addr]) {
+            diagnostics.push(Diagnostic {
+                offset: *offset,
+                message: format!("{name}: {addr:0>2x} is set but never read"),
+            });
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.offset);
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::jmp;
+
+    fn var(ty: u8, data: [u8; 8], addr: u8) -> Vec<u8> {
+        [&[0xa1, ty][..], &data, &[addr]].concat()
+    }
+
+    /// Offset and message of every warning, for a legacy cartridge with an
+    /// empty header.
+    fn warnings(code: &[u8]) -> Vec<(usize, String)> {
+        let cart = Cartridge::parse([&[0x00], code].concat()).unwrap();
+
+        analyze(&cart).into_iter().map(|d| (d.offset, d.message)).collect()
+    }
+
+    #[test]
+    fn read_before_set() {
+        assert_eq!(warnings(&[0x06, 0x00]), [(0, "SCROLLX: 00 is read before it is ever set".to_string())]);
+    }
+
+    #[test]
+    fn may_not_be_set() {
+        // READCHAR writes nil when nothing has been typed.
+        assert_eq!(warnings(&[0xd6, 0x05, 0x06, 0x05]), [(2, "SCROLLX: 05 may not be set yet".to_string())]);
+    }
+
+    #[test]
+    fn wrong_type_and_unread() {
+        let code = [
+            var(0xf0, 1f64.to_le_bytes(), 0x00),
+            var(0xe0, 1i64.to_le_bytes(), 0x01),
+            // COLLIDE needs ints for every position.
+            vec![0x09, 0x00, 0x01, 0x01, 0x01, 0x02],
+        ]
+        .concat();
+
+        assert_eq!(
+            warnings(&code),
+            [
+                (22, "COLLIDE: expected an int at 00, but it may hold a float".to_string()),
+                (22, "COLLIDE: 02 is set but never read".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn loops_are_followed() {
+        // Sets 00 after reading it, then jumps back to the read.
+        let code = [
+            vec![0x06, 0x00],
+            var(0xe0, 1i64.to_le_bytes(), 0x00),
+            jmp(0),
+        ]
+        .concat();
+
+        assert_eq!(warnings(&code), [(0, "SCROLLX: 00 may not be set yet".to_string())]);
+    }
+}
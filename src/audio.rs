@@ -0,0 +1,294 @@
+use crate::cpu::ByteCode;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Frames of audio per second. The synthesizer and music advance by one
+/// frame at a time, on a clock of their own rather than on flushes.
+pub const FRAME_RATE: u32 = 60;
+
+/// How long one frame of audio lasts.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+
+/// Number of synthesizer channels.
+pub const CHANNELS: usize = 3;
+
+pub trait AudioBackend {
+    /// Receives one frame's worth of mixed mono samples.
+    fn play(&mut self, samples: &[i16]);
+}
+
+/// Discards all audio.
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn play(&mut self, _samples: &[i16]) {}
+}
+
+/// Collects all audio and writes it to a 16-bit mono WAV file once dropped.
+pub struct WavAudio {
+    path: PathBuf,
+    samples: Vec<i16>,
+}
+
+impl WavAudio {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        WavAudio {
+            path: path.into(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn write(&self) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+
+        let data_len = self.samples.len() as u32 * 2;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&(36 + data_len).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM, mono
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        // Block align, bits per sample
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        file.write_all(&data_len.to_le_bytes())?;
+
+        for sample in &self.samples {
+            file.write_all(&sample.to_le_bytes())?;
+        }
+
+        file.flush()
+    }
+}
+
+impl AudioBackend for WavAudio {
+    fn play(&mut self, samples: &[i16]) {
+        self.samples.extend_from_slice(samples);
+    }
+}
+
+impl Drop for WavAudio {
+    fn drop(&mut self) {
+        if let Err(e) = self.write() {
+            eprintln!("Failed to write audio to {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wave {
+    Square,
+    Triangle,
+    Noise,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Channel {
+    pub wave: Wave,
+    pub freq: f64,
+    pub volume: u8,
+    /// Frames left until the channel goes silent.
+    pub frames: u32,
+    pub phase: f64,
+}
+
+impl Channel {
+    fn new(wave: Wave) -> Self {
+        Channel {
+            wave,
+            freq: 0.0,
+            volume: 0,
+            frames: 0,
+            phase: 0.0,
+        }
+    }
+}
+
+/// Square, triangle and noise channels, mixed down to one frame of samples
+/// at a time.
+pub struct Synth {
+    pub channels: [Channel; CHANNELS],
+    pub lfsr: u16,
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Synth {
+            channels: [
+                Channel::new(Wave::Square),
+                Channel::new(Wave::Triangle),
+                Channel::new(Wave::Noise),
+            ],
+            lfsr: 1,
+        }
+    }
+}
+
+impl Synth {
+    pub fn tone(&mut self, channel: usize, freq: f64, frames: u32, volume: u8) {
+        let Some(channel) = self.channels.get_mut(channel) else {
+            panic!("Unknown audio channel: {channel}")
+        };
+
+        channel.freq = freq;
+        channel.frames = frames;
+        channel.volume = volume;
+    }
+
+    /// Generates the next frame of audio and counts down every channel's
+    /// remaining duration.
+    pub fn frame(&mut self) -> [i16; SAMPLES_PER_FRAME] {
+        let mut out = [0; SAMPLES_PER_FRAME];
+
+        for sample in out.iter_mut() {
+            let mut mix = 0.0;
+
+            for channel in self.channels.iter_mut() {
+                if channel.frames == 0 || channel.freq <= 0.0 {
+                    continue;
+                }
+
+                let value = match channel.wave {
+                    Wave::Square if channel.phase < 0.5 => 1.0,
+                    Wave::Square => -1.0,
+                    Wave::Triangle => 4.0 * (channel.phase - 0.5).abs() - 1.0,
+                    Wave::Noise if self.lfsr & 1 == 1 => 1.0,
+                    Wave::Noise => -1.0,
+                };
+
+                mix += value * channel.volume as f64 / 255.0;
+
+                channel.phase += channel.freq / SAMPLE_RATE as f64;
+
+                if channel.phase >= 1.0 {
+                    channel.phase %= 1.0;
+
+                    if channel.wave == Wave::Noise {
+                        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+                    }
+                }
+            }
+
+            *sample = (mix / self.channels.len() as f64 * i16::MAX as f64) as i16;
+        }
+
+        for channel in self.channels.iter_mut() {
+            channel.frames = channel.frames.saturating_sub(1);
+        }
+
+        out
+    }
+}
+
+/// Converts a MIDI note number to its frequency in Hz (note 69 is A4).
+pub fn note_freq(note: u8) -> f64 {
+    440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)
+}
+
+/// A row of a pattern holds one note for each channel.
+/// `0x00` keeps the channel playing, `0xff` silences it.
+pub type Row = [u8; CHANNELS];
+
+pub struct Pattern {
+    /// Frames each row is held for.
+    pub speed: u8,
+    pub volume: u8,
+    pub rows: Vec<Row>,
+}
+
+/// Plays patterns from the cartridge, advancing one frame at a time.
+#[derive(Default)]
+pub struct Sequencer {
+    pub patterns: HashMap<u8, Pattern>,
+    /// Playing pattern, current row and frames left on that row.
+    pub playing: Option<(u8, usize, u8)>,
+}
+
+impl Sequencer {
+    /// Reads a pattern ID, speed, volume and row count, followed by
+    /// three note bytes per row.
+    pub fn read(&mut self, bytecode: &mut ByteCode) -> Result<(), String> {
+        let id = bytecode.next().ok_or("Expected pattern ID in music section")?;
+        let speed = bytecode.next().ok_or("Expected speed in music section")?;
+        let volume = bytecode.next().ok_or("Expected volume in music section")?;
+        let len = bytecode.next().ok_or("Expected row count in music section")?;
+
+        let mut rows = Vec::with_capacity(len as usize);
+
+        for _ in 0..len {
+            rows.push([
+                bytecode.next().ok_or("Unexpected EOF in music section")?,
+                bytecode.next().ok_or("Unexpected EOF in music section")?,
+                bytecode.next().ok_or("Unexpected EOF in music section")?,
+            ]);
+        }
+
+        if speed == 0 || rows.is_empty() {
+            return Err(format!("Pattern {id:0>2x} must have a speed and at least one row"));
+        }
+
+        self.patterns.insert(id, Pattern { speed, volume, rows });
+
+        Ok(())
+    }
+
+    pub fn play(&mut self, id: u8) {
+        if !self.patterns.contains_key(&id) {
+            panic!("Pattern {id:0>2x} is not in the cartridge");
+        }
+
+        self.playing = Some((id, 0, 0));
+    }
+
+    pub fn stop(&mut self, synth: &mut Synth) {
+        if self.playing.take().is_some() {
+            for channel in synth.channels.iter_mut() {
+                channel.frames = 0;
+            }
+        }
+    }
+
+    /// Advances by one frame, starting the next row's notes when the
+    /// current row is over. Patterns loop once they reach the end.
+    pub fn tick(&mut self, synth: &mut Synth) {
+        let Some((id, row, frames_left)) = self.playing else {
+            return;
+        };
+
+        let pattern = &self.patterns[&id];
+
+        if frames_left > 0 {
+            self.playing = Some((id, row, frames_left - 1));
+            return;
+        }
+
+        for (channel, note) in pattern.rows[row].iter().enumerate() {
+            match note {
+                0x00 => {}
+                0xff => synth.channels[channel].frames = 0,
+                note => synth.tone(channel, note_freq(*note), u32::MAX, pattern.volume),
+            }
+        }
+
+        self.playing = Some((id, (row + 1) % pattern.rows.len(), pattern.speed - 1));
+    }
+}
@@ -0,0 +1,445 @@
+use crate::{
+    audio::Sequencer,
+    color::Colour,
+    cpu::{ByteCode, ByteOption, HeaderData},
+    sprite::{SpriteSheet, Tile},
+    storage::SLOTS,
+};
+
+use std::fmt::Write;
+
+/// Version of this console, which cartridges can require a minimum of.
+pub const CONSOLE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Signature at the start of every container cartridge. No legacy header
+/// can start with `A`, so the two layouts can't be confused.
+pub const MAGIC: &[u8; 4] = b"ATC\x1a";
+pub const VERSION: u8 = 1;
+
+pub const SECTION_META: u8 = 0x01;
+pub const SECTION_CODE: u8 = 0x02;
+pub const SECTION_ASSETS: u8 = 0x03;
+pub const SECTION_MUSIC: u8 = 0x04;
+pub const SECTION_PALETTE: u8 = 0x05;
+pub const SECTION_STORAGE: u8 = 0x06;
+
+/// How a cartridge uses persistent storage.
+pub struct StorageLayout {
+    /// Number of slots the cartridge uses, up to `SLOTS`.
+    pub slots: usize,
+    /// Name of the storage file, if not the title.
+    pub key: Option<String>,
+}
+
+/// Everything that is loaded from a .atc file before it starts running.
+pub struct Cartridge {
+    pub header: HeaderData,
+    pub sprites: SpriteSheet,
+    pub music: Sequencer,
+    /// Replacement RGB values for colour codes.
+    // TODO: impl alt_colours
+    pub palette: Vec<(Colour, [u8; 3])>,
+    pub storage: StorageLayout,
+    pub code: Vec<u8>,
+}
+
+/// CRC-32 (IEEE), as used by zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+impl Cartridge {
+    fn empty() -> Self {
+        Cartridge {
+            header: HeaderData::default(),
+            sprites: SpriteSheet::default(),
+            music: Sequencer::default(),
+            palette: Vec::new(),
+            storage: StorageLayout { slots: SLOTS, key: None },
+            code: Vec::new(),
+        }
+    }
+
+    /// Parses either a container or a legacy cartridge, which is a header
+    /// terminated by `0x00` followed by code, returning what is wrong with
+    /// it if it can't be loaded.
+    pub fn parse(bytes: Vec<u8>) -> Result<Self, String> {
+        let cart = if bytes.starts_with(MAGIC) {
+            Self::parse_container(bytes)?
+        } else {
+            let mut cart = Self::empty();
+            let mut bytecode = ByteCode::new(bytes);
+
+            cart.read_header(&mut bytecode)?;
+            cart.code = bytecode.into_bytes();
+
+            cart
+        };
+
+        cart.validate()?;
+
+        Ok(cart)
+    }
+
+    /// Reads header bytes until `0x00` or the end of the input.
+    fn read_header(&mut self, bytecode: &mut ByteCode) -> Result<(), String> {
+        while let ByteOption::Some(header) = bytecode.next() && header != 0 {
+            match header {
+                0x01 => self.header.title = read_string(bytecode, 0x01)?,
+                0x02 => {
+                    self.header.repeat = true;
+                }
+                0x03 => return Err("Alt colour palette is not implemented".to_string()),
+                0x04 => self.header.keep_open = true,
+                0x05 => self.sprites.read(bytecode)?,
+                0x06 => self.music.read(bytecode)?,
+                0x07 => self.header.author = Some(read_string(bytecode, 0x07)?),
+                0x08 => self.header.version = Some(read_string(bytecode, 0x08)?),
+                0x09 => self.header.description = Some(read_string(bytecode, 0x09)?),
+                0x0a => {
+                    self.header.console_version = Some((
+                        bytecode.next().ok_or("Expected major version")?,
+                        bytecode.next().ok_or("Expected minor version")?,
+                        bytecode.next().ok_or("Expected patch version")?,
+                    ))
+                }
+                0x0b => {
+                    let mut icon = [[Colour::Transparent; 8]; 8];
+
+                    for row in icon.iter_mut() {
+                        for pix in row.iter_mut() {
+                            *pix = colour(bytecode.next().ok_or("Unexpected EOF in icon")?)?;
+                        }
+                    }
+
+                    self.header.icon = Some(icon);
+                }
+                0xd5 => self.header.debug = true,
+                any => return Err(format!("Unexpected byte ({any:x}) in header info")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the metadata is fit to show, and that this console is new
+    /// enough to run the cartridge.
+    fn validate(&self) -> Result<(), String> {
+        let header = &self.header;
+
+        let strings = [
+            ("title", Some(&header.title)),
+            ("author", header.author.as_ref()),
+            ("version", header.version.as_ref()),
+        ];
+
+        for (name, string) in strings {
+            if let Some(string) = string && string.chars().any(char::is_control) {
+                return Err(format!("Cartridge {name} {string:?} contains control characters"));
+            }
+        }
+
+        if let Some(description) = &header.description && description.chars().any(|c| c.is_control() && c != '\n') {
+            return Err("Cartridge description contains control characters".to_string());
+        }
+
+        if let Some((major, minor, patch)) = header.console_version {
+            let console: Vec<u8> = CONSOLE_VERSION.split('.').map(|n| n.parse().unwrap()).collect();
+
+            if (major, minor, patch) > (console[0], console[1], console[2]) {
+                return Err(format!(
+                    "This cartridge needs ATC Fantasy Console {major}.{minor}.{patch} or newer (this is {CONSOLE_VERSION})"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Human readable summary of the cartridge, as shown by `info`.
+    pub fn info(&self) -> String {
+        let header = &self.header;
+        let mut out = String::new();
+
+        writeln!(out, "Title:       {}", header.title).unwrap();
+
+        if let Some(author) = &header.author {
+            writeln!(out, "Author:      {author}").unwrap();
+        }
+
+        if let Some(version) = &header.version {
+            writeln!(out, "Version:     {version}").unwrap();
+        }
+
+        if let Some((major, minor, patch)) = header.console_version {
+            writeln!(out, "Requires:    ATC Fantasy Console {major}.{minor}.{patch}").unwrap();
+        }
+
+        writeln!(out, "Code:        {} bytes", self.code.len()).unwrap();
+        writeln!(out, "Sprites:     {} tiles", self.sprites.len()).unwrap();
+        writeln!(out, "Music:       {} patterns", self.music.patterns.len()).unwrap();
+        writeln!(out, "Storage:     {} slots", self.storage.slots).unwrap();
+
+        if let Some(description) = &header.description {
+            writeln!(out, "\n{description}").unwrap();
+        }
+
+        if let Some(icon) = &header.icon {
+            out.push('\n');
+            write_icon(&mut out, icon);
+        }
+
+        out
+    }
+
+    fn parse_container(bytes: Vec<u8>) -> Result<Self, String> {
+        if bytes.len() < MAGIC.len() + 6 {
+            return Err("Cartridge is too short to be a container".to_string());
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        let checksum = u32::from_le_bytes(checksum.try_into().unwrap());
+
+        if crc32(body) != checksum {
+            return Err(format!(
+                "Cartridge checksum mismatch (expected {checksum:0>8x}, found {:0>8x})",
+                crc32(body)
+            ));
+        }
+
+        let version = body[MAGIC.len()];
+        if version != VERSION {
+            return Err(format!("Unsupported cartridge version {version} (expected {VERSION})"));
+        }
+
+        let count = body[MAGIC.len() + 1];
+        let mut pos = MAGIC.len() + 2;
+
+        let mut cart = Self::empty();
+        let mut has_code = false;
+
+        for _ in 0..count {
+            let Some(&[ty, a, b, c, d]) = body.get(pos..pos + 5) else {
+                return Err(format!("Unexpected EOF in section header at byte {pos}"));
+            };
+            let len = u32::from_le_bytes([a, b, c, d]) as usize;
+
+            let Some(data) = body.get(pos + 5..pos + 5 + len) else {
+                return Err(format!("Section {ty:0>2x} at byte {pos} runs past the end of the cartridge"));
+            };
+
+            pos += 5 + len;
+
+            let mut bytecode = ByteCode::new(data.to_vec());
+
+            match ty {
+                SECTION_META => {
+                    cart.read_header(&mut bytecode)?;
+
+                    if !bytecode.at_end() {
+                        return Err(format!("Unexpected data after metadata in section at byte {}", pos - len - 5));
+                    }
+                }
+                SECTION_CODE => {
+                    if has_code {
+                        return Err("Cartridge has more than one code section".to_string());
+                    }
+
+                    has_code = true;
+                    cart.code = data.to_vec();
+                }
+                SECTION_ASSETS => {
+                    while !bytecode.at_end() {
+                        cart.sprites.read(&mut bytecode)?;
+                    }
+                }
+                SECTION_MUSIC => {
+                    while !bytecode.at_end() {
+                        cart.music.read(&mut bytecode)?;
+                    }
+                }
+                SECTION_PALETTE => {
+                    if data.len() % 4 != 0 {
+                        return Err("Palette entries must be four bytes (colour code, R, G, B)".to_string());
+                    }
+
+                    for entry in data.chunks_exact(4) {
+                        cart.palette.push((colour(entry[0])?, [entry[1], entry[2], entry[3]]));
+                    }
+                }
+                SECTION_STORAGE => {
+                    let Some((&slots, key)) = data.split_first() else {
+                        return Err("Storage section must start with a slot count".to_string());
+                    };
+
+                    if slots as usize > SLOTS {
+                        return Err(format!("Cartridge asks for {slots} storage slots, but only {SLOTS} are available"));
+                    }
+
+                    cart.storage.slots = slots as usize;
+
+                    if !key.is_empty() {
+                        let key = String::from_utf8(key.to_vec()).map_err(|_| "Storage key must be UTF-8".to_string())?;
+                        cart.storage.key = Some(key);
+                    }
+                }
+                any => return Err(format!("Unknown section type ({any:x}) at byte {}", pos - len - 5)),
+            }
+        }
+
+        if pos != body.len() {
+            return Err(format!("Unexpected data after the last section at byte {pos}"));
+        }
+
+        if !has_code {
+            return Err("Cartridge has no code section".to_string());
+        }
+
+        Ok(cart)
+    }
+}
+
+/// Colour for a code read from the cartridge.
+fn colour(num: u8) -> Result<Colour, String> {
+    Colour::try_from_hex(num).ok_or_else(|| format!("Unknown colour hexcode: {num:0>2x}!"))
+}
+
+/// Reads a string up to the closing `delim` byte. `0x00` escapes the byte
+/// after it, so that strings can contain the delimiter.
+fn read_string(bytecode: &mut ByteCode, delim: u8) -> Result<String, String> {
+    let mut string = String::new();
+
+    while let ByteOption::Some(byte) = bytecode.next() && byte != delim {
+        match byte {
+            0x00 => string.push(bytecode.next().ok_or("Unexpected EOF")?.into()),
+            any => string.push(any.into()),
+        }
+    }
+
+    Ok(string)
+}
+
+/// Draws the icon with truecolour ANSI escapes, two characters per pixel.
+fn write_icon(out: &mut String, icon: &Tile) {
+    for row in icon {
+        for clr in row {
+            if *clr == Colour::Transparent {
+                out.push_str("  ");
+            } else {
+                let [r, g, b, _] = clr.into_rgba();
+                write!(out, "\x1b[48;2;{r};{g};{b}m  \x1b[0m").unwrap();
+            }
+        }
+
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs sections into a container with the right checksum.
+    fn container(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.push(sections.len() as u8);
+
+        for (ty, data) in sections {
+            out.push(*ty);
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend(*data);
+        }
+
+        let crc = crc32(&out);
+        out.extend(crc.to_le_bytes());
+
+        out
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn legacy_layout() {
+        let cart = Cartridge::parse(vec![0x01, b'h', b'i', 0x01, 0x04, 0x00, 0xfc, 0x0c]).unwrap();
+
+        assert_eq!(cart.header.title, "hi");
+        assert!(cart.header.keep_open);
+        assert!(!cart.header.repeat);
+        assert_eq!(cart.code, [0xfc, 0x0c]);
+    }
+
+    #[test]
+    fn container_layout() {
+        let tile = [&[1][..], &[0x1b; 64]].concat();
+
+        let cart = Cartridge::parse(container(&[
+            (SECTION_META, &[0x01, b'h', b'i', 0x01, 0x02]),
+            (SECTION_CODE, &[0xfc, 0x0c]),
+            (SECTION_ASSETS, &tile),
+            (SECTION_MUSIC, &[0x07, 1, 0x80, 1, 0x45, 0x00, 0xff]),
+            (SECTION_STORAGE, &[4, b'k']),
+        ]))
+        .unwrap();
+
+        assert_eq!(cart.header.title, "hi");
+        assert!(cart.header.repeat);
+        assert_eq!(cart.code, [0xfc, 0x0c]);
+        assert_eq!(cart.sprites.len(), 1);
+        assert!(cart.music.patterns.contains_key(&0x07));
+        assert_eq!(cart.storage.slots, 4);
+        assert_eq!(cart.storage.key.as_deref(), Some("k"));
+    }
+
+    /// The message a cartridge fails to load with.
+    fn error(bytes: Vec<u8>) -> String {
+        Cartridge::parse(bytes).err().expect("cartridge should not load")
+    }
+
+    #[test]
+    fn header_errors() {
+        assert_eq!(error(vec![0x77]), "Unexpected byte (77) in header info");
+        assert_eq!(error(vec![0x03, 0x00]), "Alt colour palette is not implemented");
+        assert_eq!(error(vec![0x0a, 0x00]), "Expected minor version @ 2");
+    }
+
+    #[test]
+    fn container_checksum_mismatch() {
+        let mut bytes = container(&[(SECTION_CODE, &[0x00])]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        assert!(error(bytes).contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn container_without_code() {
+        assert_eq!(error(container(&[(SECTION_META, &[0x02])])), "Cartridge has no code section");
+    }
+
+    #[test]
+    fn container_section_too_long() {
+        let mut bytes = container(&[(SECTION_CODE, &[0x00])]);
+        // Claim the code is longer than the cartridge, then fix the checksum.
+        bytes[MAGIC.len() + 3] = 0xff;
+        bytes.truncate(bytes.len() - 4);
+        let crc = crc32(&bytes);
+        bytes.extend(crc.to_le_bytes());
+
+        assert!(error(bytes).contains("runs past the end"));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    analyze::analyze,
+    audio::CHANNELS,
+    cart::Cartridge,
+    color::Colour,
+    cpu::LAYERS,
+    decode::{walk, Operand, Step},
+    key::Key,
+    pad::{Button, PLAYERS},
+};
+
+use std::{fmt, fs::read};
+
+/// A problem found in a program, at an offset from the start of the code.
+pub struct Diagnostic {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byte {} (0x{:0>8x}): {}", self.offset, self.offset, self.message)
+    }
+}
+
+/// Describes what is wrong with an operand, if anything.
+fn check_operand(cart: &Cartridge, operand: Operand, bytes: &[u8]) -> Option<String> {
+    let byte = bytes[0];
+
+    let problem = match operand {
+        Operand::Addr if byte == 0xff => "address ff is out of bounds (memory goes up to fe)".to_string(),
+        Operand::Rows if byte > 0xfe - 7 => format!("sprite rows at {byte:0>2x}-{:0>2x} run past the end of memory", byte as usize + 7),
+        Operand::Colour if Colour::try_from_hex(byte).is_none() => format!("unknown colour code {byte:0>2x}"),
+        Operand::Key if Key::try_from_hex(byte).is_none() => format!("unknown keycode {byte:0>2x}"),
+        Operand::Type if !matches!(byte, 0xe0 | 0xf0 | 0xab | 0x8a) => format!("unknown type {byte:0>2x}"),
+        Operand::Tile | Operand::MapTile if byte as usize >= cart.sprites.len() && !(operand == Operand::MapTile && byte == 0xff) => {
+            format!("tile {byte:0>2x} is not in the sprite sheet ({} tiles)", cart.sprites.len())
+        }
+        Operand::Layer if byte as usize >= LAYERS => format!("layer {byte} out of bounds (0-{})", LAYERS - 1),
+        Operand::Channel if byte as usize >= CHANNELS => format!("audio channel {byte} out of bounds (0-{})", CHANNELS - 1),
+        Operand::Pattern if !cart.music.patterns.contains_key(&byte) => format!("pattern {byte:0>2x} is not in the cartridge"),
+        Operand::Index if byte > 7 => format!("array index {byte} out of bounds (0-7)"),
+        Operand::Slot if byte as usize >= cart.storage.slots => {
+            format!("storage slot {byte} out of bounds ({} slots)", cart.storage.slots)
+        }
+        Operand::Player if byte as usize >= PLAYERS => format!("player {byte} out of bounds (0-{})", PLAYERS - 1),
+        Operand::Button if Button::try_from_hex(byte).is_none() => format!("unknown button code {byte:0>2x}"),
+        Operand::MouseButton if byte > 2 => format!("unknown mouse button {byte}"),
+        _ => return None,
+    };
+
+    Some(problem)
+}
+
+/// Decodes the whole program ahead of time, the same way `Cpu::run` does,
+/// returning every problem found and every warning, both ordered by offset.
+/// Like `Cpu::run`, decoding carries on from the byte after an unknown
+/// opcode.
+///
+/// Jumps can land in the middle of an instruction, such as one hidden
+/// behind data that happens to decode, so the code is also decoded from
+/// every jump target until it lines back up with code already checked.
+/// Such jumps are legal, but are warned about, as they are more often
+/// a miscalculated offset than hidden code.
+pub fn check(cart: &Cartridge) -> (Vec<Diagnostic>, Vec<Diagnostic>) {
+    let code = &cart.code;
+
+    // Start and name of every instruction found by decoding from the start.
+    let lines: Vec<_> = walk(code, 0)
+        .map(|(pos, step)| match step {
+            Step::Inst { op, .. } | Step::Truncated { op, .. } => (pos, op.name),
+            Step::Unknown(_) => (pos, "unknown opcode"),
+        })
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    let mut warnings = Vec::new();
+    // Offset of the jump operand, the instruction's name and its target.
+    let mut jumps = Vec::new();
+
+    let mut checked = vec![false; code.len()];
+    let mut starts = vec![0];
+
+    while let Some(start) = starts.pop() {
+        for (pos, step) in walk(code, start) {
+            if checked[pos] {
+                break;
+            }
+
+            checked[pos] = true;
+
+            let (op, args) = match step {
+                Step::Inst { op, args, .. } => (op, args),
+                Step::Unknown(byte) => {
+                    diagnostics.push(Diagnostic {
+                        offset: pos,
+                        message: format!("unknown opcode {byte:0>2x}"),
+                    });
+                    continue;
+                }
+                Step::Truncated { op, left } => {
+                    diagnostics.push(Diagnostic {
+                        offset: pos,
+                        message: format!("{} needs {} bytes, but only {left} are left", op.name, op.size()),
+                    });
+                    // The rest of the code is part of this instruction.
+                    break;
+                }
+            };
+
+            for (offset, operand, bytes) in op.split(args) {
+                if let Some(problem) = check_operand(cart, operand, bytes) {
+                    diagnostics.push(Diagnostic {
+                        offset: pos + offset,
+                        message: format!("{}: {problem}", op.name),
+                    });
+                }
+
+                if operand == Operand::Jump {
+                    let target = u64::from_le_bytes(bytes.try_into().unwrap());
+                    jumps.push((pos + offset, op.name, target));
+
+                    if target < code.len() as u64 {
+                        starts.push(target as usize);
+                    }
+                }
+            }
+        }
+    }
+
+    for (offset, name, target) in jumps {
+        // Jumping to the very end finishes the program.
+        if target > code.len() as u64 {
+            diagnostics.push(Diagnostic {
+                offset,
+                message: format!("{name}: jumps to byte {target}, past the end of the code ({} bytes)", code.len()),
+            });
+        } else if target < code.len() as u64
+            && let Err(i) = lines.binary_search_by_key(&(target as usize), |(pos, _)| *pos)
+        {
+            // Decoding from the start always begins with byte 0.
+            let (start, inside) = lines[i - 1];
+
+            warnings.push(Diagnostic {
+                offset,
+                message: format!("{name}: jumps to byte {target}, into the middle of the {inside} at byte {start}"),
+            });
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.offset);
+    warnings.sort_by_key(|d| d.offset);
+
+    (diagnostics, warnings)
+}
+
+/// Runs the `check` command, returning the exit code: `0` if the cartridge
+/// is fine (warnings included), `1` if its code has problems and `2` if it
+/// can't be loaded.
+pub fn run(path: &str) -> i32 {
+    let bytes = match read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{path}: failed to read: {e}");
+            return 2;
+        }
+    };
+
+    let cart = match Cartridge::parse(bytes) {
+        Ok(cart) => cart,
+        Err(e) => {
+            eprintln!("{path}: failed to load: {e}");
+            return 2;
+        }
+    };
+
+    let (diagnostics, mut warnings) = check(&cart);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{path}: error at {diagnostic}");
+    }
+
+    if diagnostics.is_empty() {
+        // Control flow can only be followed once the code decodes cleanly.
+        warnings.extend(analyze(&cart));
+
+        for warning in &warnings {
+            eprintln!("{path}: warning at {warning}");
+        }
+
+        if warnings.is_empty() {
+            println!("{path}: OK");
+        } else {
+            println!("{path}: OK, {} warning(s)", warnings.len());
+        }
+
+        0
+    } else {
+        eprintln!("{path}: {} error(s)", diagnostics.len());
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::jmp;
+
+    /// A legacy cartridge with an empty header.
+    fn cart(code: &[u8]) -> Cartridge {
+        Cartridge::parse([&[0x00], code].concat()).unwrap()
+    }
+
+    /// Offset and message of every problem found.
+    fn problems(code: &[u8]) -> Vec<(usize, String)> {
+        check(&cart(code)).0.into_iter().map(|d| (d.offset, d.message)).collect()
+    }
+
+    /// Offset and message of every warning about jumps.
+    fn warnings(code: &[u8]) -> Vec<(usize, String)> {
+        check(&cart(code)).1.into_iter().map(|d| (d.offset, d.message)).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let code = [&[0xfc, 0x00, 0xfb][..], &jmp(0)].concat();
+
+        assert!(problems(&code).is_empty());
+        assert!(warnings(&code).is_empty());
+    }
+
+    #[test]
+    fn every_unknown_opcode() {
+        let problems = problems(&[0xff, 0x00, 0xee, 0xfb]);
+
+        assert_eq!(
+            problems,
+            [(0, "unknown opcode ff".to_string()), (2, "unknown opcode ee".to_string())]
+        );
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        let problems = problems(&[0xfb, 0xe3, 0x01]);
+
+        assert_eq!(problems, [(1, "JMP needs 9 bytes, but only 2 are left".to_string())]);
+    }
+
+    #[test]
+    fn jump_into_middle_of_instruction() {
+        // Byte 10 is the colour of the CLS at byte 9, which is a NOOP
+        // when jumped to.
+        let code = [&jmp(10)[..], &[0xfc, 0x00]].concat();
+
+        assert!(problems(&code).is_empty());
+        assert_eq!(
+            warnings(&code),
+            [(1, "JMP: jumps to byte 10, into the middle of the CLS at byte 9".to_string())]
+        );
+
+        // Byte 10 is the address of the SCROLLX at byte 9, which hides
+        // another SCROLLX reading an address that doesn't exist.
+        let code = [&jmp(10)[..], &[0x06, 0x06, 0xff]].concat();
+
+        assert_eq!(
+            problems(&code),
+            [
+                (11, "unknown opcode ff".to_string()),
+                (11, "SCROLLX: address ff is out of bounds (memory goes up to fe)".to_string()),
+            ]
+        );
+        assert_eq!(
+            warnings(&code),
+            [(1, "JMP: jumps to byte 10, into the middle of the SCROLLX at byte 9".to_string())]
+        );
+    }
+
+    #[test]
+    fn jump_past_end() {
+        let code = jmp(100);
+
+        assert_eq!(
+            problems(&code),
+            [(1, "JMP: jumps to byte 100, past the end of the code (9 bytes)".to_string())]
+        );
+
+        // Jumping to the very end is how a program finishes early.
+        assert!(problems(&jmp(9)).is_empty());
+    }
+
+    #[test]
+    fn jump_over_data() {
+        let code = [&jmp(11)[..], &[0xff, 0xee, 0xfb]].concat();
+
+        let problems = problems(&code);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems.iter().all(|(_, message)| message.starts_with("unknown opcode")));
+    }
+
+    #[test]
+    fn bad_operands() {
+        // CLS with an unknown colour, then KEY with an unknown keycode
+        // writing to an address that doesn't exist.
+        let problems = problems(&[0xfc, 0x12, 0xd0, 0x77, 0xff]);
+
+        assert_eq!(
+            problems,
+            [
+                (1, "CLS: unknown colour code 12".to_string()),
+                (3, "KEY: unknown keycode 77".to_string()),
+                (4, "KEY: address ff is out of bounds (memory goes up to fe)".to_string()),
+            ]
+        );
+    }
+}
@@ -38,7 +38,11 @@ impl Colour {
     }
 
     pub fn from_hex(num: u8) -> Self {
-        match num {
+        Self::try_from_hex(num).unwrap_or_else(|| panic!("Unknown colour hexcode: {num:0>2x}!"))
+    }
+
+    pub fn try_from_hex(num: u8) -> Option<Self> {
+        Some(match num {
             0x00 => Black,
             0xff => White,
             0x0f => Gray,
@@ -56,7 +60,29 @@ impl Colour {
             0xac => Purple,
             0xbf => Pink,
             0x99 => Transparent,
-            any => panic!("Unknown colour hexcode: {any:0>2x}!"),
+            _ => return None,
+        })
+    }
+
+    pub fn to_hex(self) -> u8 {
+        match self {
+            Black => 0x00,
+            White => 0xff,
+            Gray => 0x0f,
+            LightGray => 0x1f,
+            DarkRed => 0x0a,
+            Red => 0x1a,
+            DarkGreen => 0x0b,
+            Green => 0x1b,
+            DarkBlue => 0x0c,
+            Blue => 0x1c,
+            Cyan => 0x2c,
+            Yellow => 0xab,
+            Orange => 0xfa,
+            Brown => 0x30,
+            Purple => 0xac,
+            Pink => 0xbf,
+            Transparent => 0x99,
         }
     }
 }
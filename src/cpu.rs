@@ -1,10 +1,63 @@
-use crate::{color::Colour, key::Key, render::RenderBackend, HEIGHT, RES, WIDTH};
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    audio::{AudioBackend, NullAudio, Sequencer, Synth, FRAME},
+    cart::Cartridge,
+    color::Colour,
+    decode::{self, Instruction, Program},
+    key::KeyState,
+    pad::PadMap,
+    render::{Hotkey, Rect, RenderBackend},
+    screenshot,
+    sprite::{self, Mask, SpriteSheet, Tile, TileMap, MAP_SIZE},
+    storage::{self, Storage},
+    HEIGHT, RES, WIDTH,
+};
+
+/// Number of framebuffer layers. Layer 0 is drawn at the bottom.
+pub const LAYERS: usize = 3;
+
+/// Instructions run between checks for audio that is due, since checking
+/// the clock after every one would slow programs down.
+const AUDIO_STEPS: usize = 1024;
 
 pub struct Cpu<T: RenderBackend> {
     pub memory: [Mem; 255],
+    /// The composited frame, rebuilt from the layers on every flush.
     pub buf: [Colour; RES],
+    pub layers: Vec<[Colour; RES]>,
+    /// Layer that pixel, sprite and clear instructions draw to.
+    pub layer: usize,
+    /// Part of the screen drawn to since the last flush, if any.
+    pub dirty: Option<Rect>,
+    /// Number of flushes so far, and how many of them had nothing to draw.
+    pub flushes: usize,
+    pub skipped: usize,
     pub window: T,
     pub header: HeaderData,
+    pub sprites: SpriteSheet,
+    pub tilemap: TileMap,
+    pub audio: Box<dyn AudioBackend>,
+    pub synth: Synth,
+    pub music: Sequencer,
+    /// When audio was last played up to. Audio keeps its own time, one
+    /// frame every 1/60th of a second, whatever the program is doing.
+    pub audio_clock: Instant,
+    pub keys: KeyState,
+    pub pads: PadMap,
+    /// Where the save and load state hotkeys write and read snapshots.
+    pub state_path: PathBuf,
+    /// Snapshot to restore before the program starts.
+    pub resume: Option<PathBuf>,
+    /// Screenshot hotkey images are saved as `<screenshot_base>-<frame>.png`.
+    pub screenshot_base: PathBuf,
+    /// Flush to save a screenshot on, and where to, after which the
+    /// program stops.
+    pub screenshot_at: Option<(usize, PathBuf)>,
+    pub storage: Storage,
+    /// Directory the persistent storage file is kept in. Storage is
+    /// not saved if this is `None`.
+    pub storage_dir: Option<PathBuf>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -26,20 +79,34 @@ impl Mem {
     }
 }
 
+/// Title of cartridges that don't set one.
+pub const DEFAULT_TITLE: &str = "ATC Fantasy Console";
+
 pub struct HeaderData {
-    title: String,
-    repeat: bool,
+    pub title: String,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Oldest console version (major, minor, patch) the cartridge runs on.
+    pub console_version: Option<(u8, u8, u8)>,
+    pub icon: Option<Tile>,
+    pub repeat: bool,
     // TODO: impl alt_colours
     #[allow(dead_code)]
-    alt_colours: bool,
-    keep_open: bool,
-    debug: bool
+    pub alt_colours: bool,
+    pub keep_open: bool,
+    pub debug: bool
 }
 
 impl Default for HeaderData {
     fn default() -> Self {
         HeaderData {
-            title: "ATC Fantasy Console".into(),
+            title: DEFAULT_TITLE.into(),
+            author: None,
+            version: None,
+            description: None,
+            console_version: None,
+            icon: None,
             repeat: false,
             alt_colours: false,
             keep_open: false,
@@ -48,6 +115,25 @@ impl Default for HeaderData {
     }
 }
 
+impl HeaderData {
+    /// Title, version and author, e.g. `Shrimp v1.2 by T-O-R-U-S`.
+    pub fn window_title(&self) -> String {
+        let mut title = self.title.clone();
+
+        if let Some(version) = &self.version {
+            title.push(' ');
+            title.push_str(version);
+        }
+
+        if let Some(author) = &self.author {
+            title.push_str(" by ");
+            title.push_str(author);
+        }
+
+        title
+    }
+}
+
 pub struct ByteCode(Vec<u8>, usize);
 
 pub enum ByteOption<'a> {
@@ -69,6 +155,14 @@ impl ByteOption<'_> {
             ByteOption::None(bytecode) => panic!("{panic_str} @ {}", bytecode.1),
         }
     }
+
+    /// Like `expect`, but returns the message rather than panicking.
+    pub fn ok_or(self, err: &str) -> Result<u8, String> {
+        match self {
+            ByteOption::Some(num) => Ok(num),
+            ByteOption::None(bytecode) => Err(format!("{err} @ {}", bytecode.1)),
+        }
+    }
 }
 
 impl ByteCode {
@@ -81,138 +175,234 @@ impl ByteCode {
         ByteOption::Some(*out)
     }
 
-    pub fn shift(&mut self) -> ByteOption {
-        let out = self.0.remove(0);
-
-        ByteOption::Some(out)
-    }
-
-    pub fn jmp(&mut self, byte: usize) {
-        self.1 = byte
+    /// Whether every byte has been read.
+    pub fn at_end(&self) -> bool {
+        self.1 >= self.0.len()
     }
 
     pub fn new(bytecode: Vec<u8>) -> Self {
         Self(bytecode, 0)
     }
+
+    /// The bytes that haven't been read yet.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.0.split_off(self.1.min(self.0.len()))
+    }
 }
 
 impl<T: RenderBackend> Cpu<T> {
-    pub fn new() -> Self {
+    pub fn new(window: T) -> Self {
         Cpu {
             memory: [Mem::Nil; 255],
             buf: [Colour::Green; 65025],
+            layers: {
+                let mut layers = vec![[Colour::Transparent; RES]; LAYERS];
+                layers[0] = [Colour::Green; RES];
+                layers
+            },
+            layer: 0,
+            dirty: Some(Rect::FULL),
+            flushes: 0,
+            skipped: 0,
             header: HeaderData::default(),
-            window: T::new(),
+            window,
+            sprites: SpriteSheet::default(),
+            tilemap: TileMap::default(),
+            audio: Box::new(NullAudio),
+            synth: Synth::default(),
+            music: Sequencer::default(),
+            audio_clock: Instant::now(),
+            keys: KeyState::default(),
+            pads: PadMap::default(),
+            state_path: PathBuf::from("atc_console.state"),
+            resume: None,
+            screenshot_base: PathBuf::from("atc_console"),
+            screenshot_at: None,
+            storage: Storage::default(),
+            storage_dir: None,
         }
     }
 
-    pub fn run(&mut self, bytecode: Vec<u8>) {
-        let mut bytecode = ByteCode::new(bytecode);
+    /// Advances the music and tones by every frame that is due by the
+    /// audio clock, and plays them.
+    fn play_audio(&mut self) {
+        while self.audio_clock.elapsed() >= FRAME {
+            self.audio_clock += FRAME;
 
-        while let ByteOption::Some(header) = bytecode.shift() && header != 0 {
-            match header {
-                0x01 => {
-                    let mut string = String::new();
-                    while let ByteOption::Some(byte) = bytecode.shift() && byte != 0x01 {
-                        match byte {
-                            0x00 => string.push(bytecode.shift().unwrap().into()),
-                            any => string.push(any.into()),
-                        }
-                    }
+            self.music.tick(&mut self.synth);
+            let samples = self.synth.frame();
+            self.audio.play(&samples);
+        }
+    }
+
+    fn int_at(&self, addr: usize) -> i64 {
+        let Mem::Int(int) = self.memory[addr] else {
+            panic!("Expected int at addr {addr}, but instead found {:?}", self.memory[addr])
+        };
+
+        int
+    }
+
+    /// Reads eight consecutive byte arrays starting at `addr` as sprite rows.
+    fn mask_at(&self, addr: usize) -> Mask {
+        let mut mask = [[0; 8]; 8];
+
+        for (i, row) in mask.iter_mut().enumerate() {
+            let Mem::ByteArr(arr) = self.memory[addr + i] else {
+                panic!("Expected byte array at addr {}, but instead found {:?}", addr + i, self.memory[addr + i])
+            };
+
+            *row = arr;
+        }
+
+        mask
+    }
 
-                    self.header.title = string;
+    /// Adds to the part of the screen that needs to be redrawn on flush.
+    fn mark(&mut self, rect: Rect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+
+    /// Flattens the layers into `buf` within `rect`, top layer first. Pixels
+    /// that are transparent on every layer fall through to the background
+    /// tile map.
+    pub fn compose(&mut self, rect: Rect) {
+        let (top, below) = self.layers.split_last().unwrap();
+
+        for y in rect.rows() {
+            let row = y * WIDTH + rect.x0..y * WIDTH + rect.x1;
+            let buf = &mut self.buf[row.clone()];
+
+            // Whole rows at a time, rather than pixel by pixel, so
+            // that the copies and comparisons can be vectorised.
+            buf.copy_from_slice(&top[row.clone()]);
+
+            for layer in below.iter().rev() {
+                for (pix, clr) in buf.iter_mut().zip(&layer[row.clone()]) {
+                    if *pix == Colour::Transparent {
+                        *pix = *clr;
+                    }
                 }
-                0x02 => {
-                    self.header.repeat = true;
+            }
+
+            for (x, pix) in (rect.x0..).zip(buf.iter_mut()) {
+                if *pix == Colour::Transparent {
+                    *pix = self.tilemap.pixel(&self.sprites, x, y);
                 }
-                0x03 => todo!("ERR: Alt colour pallette not implemented!"),
-                0x04 => self.header.keep_open = true,
-                0xd5 => self.header.debug = true,
-                any => panic!("Unexpected byte ({any:x}) in header info"),
             }
         }
+    }
+
+    pub fn run(&mut self, bytecode: Vec<u8>) {
+        let cart = Cartridge::parse(bytecode).unwrap_or_else(|e| panic!("Failed to load cartridge: {e}"));
+
+        self.header = cart.header;
+        self.sprites = cart.sprites;
+        self.music = cart.music;
+
+        if let Some(dir) = &self.storage_dir {
+            let key = cart.storage.key.as_deref().unwrap_or(&self.header.title);
+            let path = dir.join(storage::file_name(key, &cart.code));
+
+            self.storage = Storage::open(path, cart.storage.slots);
+        }
+
+        self.window.set_title(&self.header.window_title());
+
+        let mut program = Program::decode(&cart.code);
+        let code_hash = storage::hash(&cart.code);
+        // Byte offset of the next instruction to run.
+        let mut pc = 0;
+        // Instructions run so far.
+        let mut steps = 0;
+
+        if let Some(path) = self.resume.take() {
+            pc = self
+                .load_state(&path, code_hash)
+                .unwrap_or_else(|e| panic!("Failed to load state: {e}"));
+        }
+
+        // Time spent loading doesn't count towards the audio.
+        self.audio_clock = Instant::now();
 
         'a: loop {
 
-            while let ByteOption::Some(code) = bytecode.next() {
+            while let Some((inst, next)) = program.get(pc) {
                 if !self.window.is_open() {
                     break 'a;
                 }
 
-                match code {
-                    0x00 => {}
-                    0x01 => {
-                        let x: usize = bytecode.next().unwrap().into();
-                        let y: usize = bytecode.next().unwrap().into();
-                        let clr = Colour::from_hex(bytecode.next().unwrap());
+                pc = *next;
+
+                // Offset of the next instruction's byte, for error messages.
+                let pos = pc;
+
+                match *inst {
+                    Instruction::Noop => {}
+                    Instruction::ConstPixel { x, y, clr } => {
+                        let (x, y) = (x as usize, y as usize);
 
                         if clr != Colour::Transparent {
-                            self.buf[x + y * WIDTH] = clr;
+                            // Pixels past the right edge land at the start of the next row.
+                            let i = x + y * WIDTH;
+
+                            self.layers[self.layer][i] = clr;
+                            self.mark(Rect::pixel(i));
                         };
 
                         if self.header.debug {
                             println!("CPIX CALL :: ({x}, {y}) => {clr:x?}");
                         }
                     }
-                    0x02 => {
-                        let x_byte = bytecode.next().unwrap() as usize;
-                        let y_byte = bytecode.next().unwrap() as usize;
-                        let x: usize = self.memory[x_byte].to_num().expect(&format!(
-                            "Failed to cast {:?} to num at byte {} (attempted to access address x: {x_byte:0>2x}, y: {y_byte:0>2x})",
-                            self.memory[x_byte], bytecode.1
+                    Instruction::Pixel { x: x_byte, y: y_byte, clr } => {
+                        let (x_byte, y_byte) = (x_byte as usize, y_byte as usize);
+                        let x: usize = self.memory[x_byte].to_num().unwrap_or_else(|mem| panic!(
+                            "Failed to cast {mem:?} to num at byte {pos} (attempted to access address x: {x_byte:0>2x}, y: {y_byte:0>2x})"
                         )) as usize;
-                        let y: usize = self.memory[y_byte].to_num().expect(&format!(
-                            "Failed to cast {:?} to num at byte {} (attempted to access address y: {y_byte:0>2x}, x: {x_byte:0>2x})",
-                            self.memory[y_byte], bytecode.1
+                        let y: usize = self.memory[y_byte].to_num().unwrap_or_else(|mem| panic!(
+                            "Failed to cast {mem:?} to num at byte {pos} (attempted to access address y: {y_byte:0>2x}, x: {x_byte:0>2x})"
                         )) as usize;
-                        let clr = bytecode.next().unwrap();
-
-                        let clr = Colour::from_hex(clr);
 
                         if clr != Colour::Transparent {
-                            self.buf[(x % WIDTH) + (y % HEIGHT) * WIDTH] = clr;
+                            self.layers[self.layer][(x % WIDTH) + (y % HEIGHT) * WIDTH] = clr;
+                            self.mark(Rect::new(x % WIDTH, y % HEIGHT, 1, 1));
                         }
 
                         if self.header.debug {
                             println!("PIX CALL :: ({x} @ {x_byte:0>2x}, {y} @ {y_byte:0>2x}) => {clr:x?}");
                         }
                     }
-                    0x03 => {
+                    Instruction::Sprite { rows, x, y } => {
                         let mut byte_arr = [[0; 8]; 8];
 
-                        for i in 0..8 {
-                            let arr_addr = bytecode.next().unwrap() as usize;
-
-                            let Mem::ByteArr(arr) = self.memory[arr_addr] else {
+                        for (row, arr_addr) in byte_arr.iter_mut().zip(rows) {
+                            let Mem::ByteArr(arr) = self.memory[arr_addr as usize] else {
                                 panic!("Expected byte array.")
                             };
 
-                            byte_arr[i] = arr;
+                            *row = arr;
                         }
 
-                        let x_addr = bytecode.next().unwrap() as usize;
-                        let y_addr = bytecode.next().unwrap() as usize;
-
-                        let Mem::Int(x) = self.memory[x_addr] else {
-                            panic!("Expected int at addr {x_addr}, but instead found {:?}", self.memory[x_addr])
-                        };
-
-                        let Mem::Int(y) = self.memory[y_addr] else {
-                            panic!("Expected int at addr {y_addr}, but instead found {:?}", self.memory[y_addr])
-                        };
+                        let x = self.int_at(x as usize);
+                        let y = self.int_at(y as usize);
 
                         for (y_offset, row) in byte_arr.iter().enumerate() {
                             for (x_offset, pix) in row.iter().enumerate() {
-                                let x = x as usize + x_offset;
-                                let y = (y as usize + y_offset) * WIDTH;
-
                                 let clr = Colour::from_hex(*pix);
 
                                 if clr == Colour::Transparent {
                                     continue;
                                 }
 
-                                self.buf[x + y] = clr;
+                                // Sprites that run off the left or right edge spill onto
+                                // the end of the previous row or the start of the next.
+                                let i = ((y + y_offset as i64) * WIDTH as i64 + x + x_offset as i64) as usize;
+
+                                self.layers[self.layer][i] = clr;
+                                self.mark(Rect::pixel(i));
                             }
                         }
 
@@ -220,280 +410,260 @@ impl<T: RenderBackend> Cpu<T> {
                             println!("SPR CALL :: ({x}, {y}) @ {byte_arr:0>2x?}");
                         }
                     }
-                    0xf0 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::SpriteTile { tile: tile_id, x, y } => {
+                        let x = self.int_at(x as usize);
+                        let y = self.int_at(y as usize);
+
+                        let tile = self.sprites.get(tile_id);
 
-                        self.memory[addr] = Mem::Float(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) / self.memory[rhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )),
-                        );
+                        // Negative positions wrap around from the other edge.
+                        let left = x.rem_euclid(WIDTH as i64) as usize;
+                        let top = y.rem_euclid(HEIGHT as i64) as usize;
+
+                        for (y_offset, row) in tile.iter().enumerate() {
+                            for (x_offset, clr) in row.iter().enumerate() {
+                                if *clr == Colour::Transparent {
+                                    continue;
+                                }
+
+                                let x = (left + x_offset) % WIDTH;
+                                let y = (top + y_offset) % HEIGHT;
+
+                                self.layers[self.layer][x + y * WIDTH] = *clr;
+                            }
+                        }
+
+                        self.mark(Rect::wrapping(left, top, 8, 8));
 
                         if self.header.debug {
-                            println!("FDIV CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("SPRI CALL :: TILE {tile_id:0>2x} => ({x}, {y})");
                         }
                     }
-                    0xf1 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Tile { col, row, tile: tile_id } => {
+                        let col = (self.memory[col as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as i64)
+                            .rem_euclid(MAP_SIZE as i64) as usize;
+                        let row = (self.memory[row as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as i64)
+                            .rem_euclid(MAP_SIZE as i64) as usize;
+
+                        self.tilemap.tiles[row][col] = match tile_id {
+                            0xff => None,
+                            id => {
+                                // Panics early if the tile does not exist,
+                                // rather than on the next flush.
+                                self.sprites.get(id);
+                                Some(id)
+                            }
+                        };
 
-                        self.memory[addr] = Mem::Float(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) - self.memory[rhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )),
-                        );
+                        // The tile can show up anywhere once scrolled.
+                        self.mark(Rect::FULL);
 
                         if self.header.debug {
-                            println!("FSUB CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("TILE CALL :: ({col}, {row}) => {tile_id:0>2x}");
                         }
                     }
-                    0xf2 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::ScrollX(addr) | Instruction::ScrollY(addr) => {
+                        let offset = (self.memory[addr as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as i64)
+                            .rem_euclid((MAP_SIZE * 8) as i64) as usize;
 
-                        self.memory[addr] = Mem::Float(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) + self.memory[rhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )),
-                        );
+                        let is_x = matches!(inst, Instruction::ScrollX(_));
+
+                        if is_x {
+                            self.tilemap.scroll_x = offset;
+                        } else {
+                            self.tilemap.scroll_y = offset;
+                        }
+
+                        self.mark(Rect::FULL);
 
                         if self.header.debug {
-                            println!("FADD CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            let axis = if is_x { "X" } else { "Y" };
+                            println!("SCROLL{axis} CALL :: {offset} @ {addr:0>2x}");
                         }
                     }
-                    0xf3 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Layer(layer) => {
+                        let layer = layer as usize;
+
+                        if layer >= LAYERS {
+                            panic!("Layer {layer} out of bounds at byte {pos}. (Zero based indexing!)")
+                        }
 
-                        self.memory[addr] = Mem::Float(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) * self.memory[rhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )),
-                        );
+                        self.layer = layer;
 
                         if self.header.debug {
-                            println!("FMUL CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("LAYER CALL :: SELECTED LAYER {layer}");
                         }
                     }
-                    0xf4 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Collide { ax, ay, bx, by, out } => {
+                        let out = out as usize;
+
+                        let (ax, ay) = (self.int_at(ax as usize), self.int_at(ay as usize));
+                        let (bx, by) = (self.int_at(bx as usize), self.int_at(by as usize));
 
-                        self.memory[addr] = Mem::Int(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) as i64
-                                / self.memory[rhs].to_num().expect(&format!(
-                                    "Failed to cast {:?} to number at byte {}",
-                                    self.memory[lhs], bytecode.1
-                                )) as i64,
-                        );
+                        let hit = sprite::boxes_overlap((ax, ay), (bx, by));
+
+                        self.memory[out] = Mem::Int(hit as i64);
 
                         if self.header.debug {
-                            println!("DIV CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("COLLIDE CALL :: ({ax}, {ay}) AND ({bx}, {by}) => {out:0>2x} WHICH IS {:?}", self.memory[out]);
                         }
                     }
-                    0xf5 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::PixelCollide { ax, ay, a_rows, bx, by, b_rows, out } => {
+                        let a_mask = self.mask_at(a_rows as usize);
+                        let b_mask = self.mask_at(b_rows as usize);
+                        let out = out as usize;
+
+                        let (ax, ay) = (self.int_at(ax as usize), self.int_at(ay as usize));
+                        let (bx, by) = (self.int_at(bx as usize), self.int_at(by as usize));
+
+                        let hit = sprite::masks_overlap((ax, ay), &a_mask, (bx, by), &b_mask);
 
-                        self.memory[addr] = Mem::Int(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) as i64
-                                - self.memory[rhs].to_num().expect(&format!(
-                                    "Failed to cast {:?} to number at byte {}",
-                                    self.memory[lhs], bytecode.1
-                                )) as i64,
-                        );
+                        self.memory[out] = Mem::Int(hit as i64);
 
                         if self.header.debug {
-                            println!("SUB CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("PCOLLIDE CALL :: ({ax}, {ay}) AND ({bx}, {by}) => {out:0>2x} WHICH IS {:?}", self.memory[out]);
                         }
                     }
-                    0xf6 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Peek { x, y, out } => {
+                        let out = out as usize;
 
-                        self.memory[addr] = Mem::Int(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) as i64
-                                + self.memory[rhs].to_num().expect(&format!(
-                                    "Failed to cast {:?} to number at byte {}",
-                                    self.memory[lhs], bytecode.1
-                                )) as i64,
-                        );
+                        let x = (self.memory[x as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as i64)
+                            .rem_euclid(WIDTH as i64) as usize;
+                        let y = (self.memory[y as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as i64)
+                            .rem_euclid(HEIGHT as i64) as usize;
+
+                        // What is on screen, as of the last flush.
+                        let clr = self.buf[x + y * WIDTH];
+
+                        self.memory[out] = Mem::Int(clr.to_hex() as i64);
 
                         if self.header.debug {
-                            println!("ADD CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("PEEKPIX CALL :: ({x}, {y}) => {out:0>2x} WHICH IS {clr:x?}");
                         }
                     }
-                    0xf7 => {
-                        let lhs = bytecode.next().unwrap() as usize;
-                        let rhs = bytecode.next().unwrap() as usize;
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Tone { channel, freq, duration, volume } => {
+                        let channel = channel as usize;
+
+                        let freq = self.memory[freq as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}"));
+                        let frames = self.memory[duration as usize]
+                            .to_num()
+                            .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to num at byte {pos}")) as u32;
 
-                        self.memory[addr] = Mem::Int(
-                            self.memory[lhs].to_num().expect(&format!(
-                                "Failed to cast {:?} to number at byte {}",
-                                self.memory[lhs], bytecode.1
-                            )) as i64
-                                * self.memory[rhs].to_num().expect(&format!(
-                                    "Failed to cast {:?} to number at byte {}",
-                                    self.memory[lhs], bytecode.1
-                                )) as i64,
-                        );
+                        self.synth.tone(channel, freq, frames, volume);
 
                         if self.header.debug {
-                            println!("MUL CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
+                            println!("TONE CALL :: CHANNEL {channel} AT {freq} HZ FOR {frames} FRAMES, VOLUME {volume:0>2x}");
                         }
                     }
-                    0xb0 => {
-                        let addr_num = bytecode.next().unwrap() as usize;
-                        let out_addr = bytecode.next().unwrap() as usize;
-
-                        self.memory[out_addr] = if self.memory[addr_num] == Mem::Int(0x00) {
-                            Mem::Int(0x01)
-                        } else {
-                            Mem::Int(0x00)
-                        };
+                    Instruction::PlayMusic(pattern) => {
+                        self.music.play(pattern);
 
                         if self.header.debug {
-                            println!("NOT CALL :: {:?} @ {addr_num} => {out_addr}", self.memory[addr_num]);
+                            println!("MUSIC CALL :: PLAYING PATTERN {pattern:0>2x}");
                         }
                     }
-                    0xb1 => {
-                        let lhs_addr = bytecode.next().unwrap() as usize;
-                        let rhs_addr = bytecode.next().unwrap() as usize;
+                    Instruction::StopMusic => {
+                        self.music.stop(&mut self.synth);
 
-                        let lhs = self.memory[lhs_addr];
-                        let rhs = self.memory[rhs_addr];
+                        if self.header.debug {
+                            println!("MUSIC CALL :: STOPPED");
+                        }
+                    }
+                    Instruction::Arith { code, lhs, rhs, out } => {
+                        let (lhs, rhs, addr) = (lhs as usize, rhs as usize, out as usize);
 
-                        let out = bytecode.next().unwrap() as usize;
+                        let num = |addr: usize| {
+                            self.memory[addr]
+                                .to_num()
+                                .unwrap_or_else(|mem| panic!("Failed to cast {mem:?} to number at byte {pos}"))
+                        };
 
-                        self.memory[out] = match lhs.to_num() > rhs.to_num() {
-                            true => Mem::Int(0x01),
-                            false => Mem::Int(0x00),
+                        let (a, b) = (num(lhs), num(rhs));
+
+                        self.memory[addr] = match code {
+                            0xf0 => Mem::Float(a / b),
+                            0xf1 => Mem::Float(a - b),
+                            0xf2 => Mem::Float(a + b),
+                            0xf3 => Mem::Float(a * b),
+                            0xf4 => Mem::Int(a as i64 / b as i64),
+                            0xf5 => Mem::Int(a as i64 - b as i64),
+                            0xf6 => Mem::Int(a as i64 + b as i64),
+                            _ => Mem::Int(a as i64 * b as i64),
                         };
 
                         if self.header.debug {
-                            println!("GT CALL :: ({:?} @ {lhs:0>2x?} > {:?} @ {rhs:0>2x?}) => {out:0>2x}", self.memory[lhs_addr], self.memory[rhs_addr]);
+                            let name = decode::op(code).unwrap().name;
+                            println!("{name} CALL :: ({:?} @ {lhs:0>2x}, {:?} @ {rhs:0>2x}) => {addr:0>2x}", self.memory[lhs], self.memory[rhs]);
                         }
                     }
-                    0xb2 => {
-                        let lhs_addr = bytecode.next().unwrap() as usize;
-                        let rhs_addr = bytecode.next().unwrap() as usize;
-
-                        let lhs = self.memory[lhs_addr];
-                        let rhs = self.memory[rhs_addr];
-
-                        let out = bytecode.next().unwrap() as usize;
+                    Instruction::Not { addr, out } => {
+                        let (addr_num, out_addr) = (addr as usize, out as usize);
 
-                        self.memory[out] = match lhs.to_num() < rhs.to_num() {
-                            true => Mem::Int(0x01),
-                            false => Mem::Int(0x00),
+                        self.memory[out_addr] = if self.memory[addr_num] == Mem::Int(0x00) {
+                            Mem::Int(0x01)
+                        } else {
+                            Mem::Int(0x00)
                         };
 
                         if self.header.debug {
-                            println!("LT CALL :: ({:?} @ {lhs:0>2x?} > {:?} @ {rhs:0>2x?}) => {out:0>2x}", self.memory[lhs_addr], self.memory[rhs_addr]);
+                            println!("NOT CALL :: {:?} @ {addr_num} => {out_addr}", self.memory[addr_num]);
                         }
                     }
-                    0xa1 => {
-                        let ty = bytecode.next().unwrap();
+                    Instruction::Gt { lhs: lhs_addr, rhs: rhs_addr, out } | Instruction::Lt { lhs: lhs_addr, rhs: rhs_addr, out } => {
+                        let (lhs_addr, rhs_addr, out) = (lhs_addr as usize, rhs_addr as usize, out as usize);
 
-                        let data: [u8; 8] = [
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                        ];
+                        let lhs = self.memory[lhs_addr];
+                        let rhs = self.memory[rhs_addr];
 
-                        let addr = bytecode.next().unwrap() as usize;
+                        let is_gt = matches!(inst, Instruction::Gt { .. });
 
-                        let out = match ty {
-                            0xe0 => Mem::Int(i64::from_le_bytes(data)),
-                            0xf0 => Mem::Float(f64::from_le_bytes(data)),
-                            0xab => Mem::Str(data.map(|e| e as char)),
-                            0x8a => Mem::ByteArr(data),
-                            any => panic!("Unknown type: {any:x}"),
+                        let result = if is_gt {
+                            lhs.to_num() > rhs.to_num()
+                        } else {
+                            lhs.to_num() < rhs.to_num()
                         };
 
-                        self.memory[addr] = out;
+                        self.memory[out] = Mem::Int(result as i64);
 
                         if self.header.debug {
-                            println!("VAR CALL :: {data:0>2x?} of type {ty:0>2x} @ {addr} => {:?}", out);
+                            let name = if is_gt { "GT" } else { "LT" };
+                            println!("{name} CALL :: ({:?} @ {lhs:0>2x?} > {:?} @ {rhs:0>2x?}) => {out:0>2x}", self.memory[lhs_addr], self.memory[rhs_addr]);
                         }
                     }
-                    0xa2 => {
-                        let ty = bytecode.next().unwrap();
+                    Instruction::Var { ty, data, value, addr } => {
+                        let addr = addr as usize;
 
-                        let data: [u8; 8] = [
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                        ];
+                        self.memory[addr] = value;
 
-                        let addr = bytecode.next().unwrap() as usize;
+                        if self.header.debug {
+                            println!("VAR CALL :: {data:0>2x?} of type {ty:0>2x} @ {addr} => {:?}", value);
+                        }
+                    }
+                    Instruction::Let { ty, data, value, addr } => {
+                        let addr = addr as usize;
 
                         if self.memory[addr] == Mem::Nil {
-                            let out = match ty {
-                                0xe0 => Mem::Int(i64::from_le_bytes(data)),
-                                0xf0 => Mem::Float(f64::from_le_bytes(data)),
-                                0xab => Mem::Str(data.map(|e| e as char)),
-                                0x8a => Mem::ByteArr(data),
-                                any => panic!("Unknown type: {any:x}"),
-                            };
-
-                            self.memory[addr] = out;
+                            self.memory[addr] = value;
                         }
 
                         if self.header.debug {
                             println!("LET CALL :: {data:0>2x?} of type {ty:0>2x} @ {addr} => {:?}", self.memory[addr]);
                         }
                     }
-                    0xa3 => {
-                        let arr_addr = bytecode.next().unwrap() as usize;
-                        let idx @ 0..=7 = bytecode.next().unwrap() as usize else {
-                                                            panic!("Array index out of bounds. (Zero based indexing!)")
-                                                        };
-                        let item = bytecode.next().unwrap();
+                    Instruction::ArrayWrite { addr: arr_addr, idx, item } => {
+                        let arr_addr = arr_addr as usize;
 
                         println!("ARRW INFO :: {arr_addr:0>2x}[{idx}] WAS {:?}", self.memory[arr_addr]);
 
@@ -509,83 +679,68 @@ impl<T: RenderBackend> Cpu<T> {
                             println!("ARRW CALL :: {arr_addr:0>2x}[{idx}] = {item:0>2x}");
                         }
                     }
-                    0xe1 => {
-                        let var_addr = bytecode.next().unwrap() as usize;
+                    Instruction::Store { slot, addr } => {
+                        let addr = addr as usize;
+
+                        self.storage.store(slot, self.memory[addr]);
+
+                        if self.header.debug {
+                            println!("PSTORE CALL :: {:?} @ {addr:0>2x} => SLOT {slot}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::Load { slot, addr } => {
+                        let addr = addr as usize;
 
-                        let jmp_byte = usize::from_le_bytes([
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                        ]);
+                        self.memory[addr] = self.storage.load(slot);
+
+                        if self.header.debug {
+                            println!("PLOAD CALL :: SLOT {slot} => {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::JumpIf { cond: var_addr, byte: jmp_byte } => {
+                        let var_addr = var_addr as usize;
 
                         if self.memory[var_addr] == Mem::Int(0x01) {
-                            bytecode.jmp(jmp_byte)
+                            pc = jmp_byte;
                         }
+
                         if self.header.debug {
                             println!("TJMP CALL :: TO {jmp_byte} IF {var_addr:0>2x} WHICH IS {:0>2x?}", self.memory[var_addr]);
                         }
                     }
-                    0xe2 => {
-                        let var_addr = bytecode.next().unwrap() as usize;
-
-                        let jmp_byte = usize::from_le_bytes([
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                        ]);
+                    Instruction::JumpIfNot { cond: var_addr, byte: jmp_byte } => {
+                        let var_addr = var_addr as usize;
 
                         if self.memory[var_addr] != Mem::Int(0x01) {
-                            bytecode.jmp(jmp_byte)
+                            pc = jmp_byte;
                         }
 
                         if self.header.debug {
                             println!("FJMP CALL :: TO {jmp_byte} IF NOT {var_addr:0>2x} WHICH IS {:?}", self.memory[var_addr]);
                         }
                     }
-                    0xe3 => {
-                        let jmp_byte = usize::from_le_bytes([
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                            bytecode.next().unwrap(),
-                        ]);
-
-                        bytecode.jmp(jmp_byte);
+                    Instruction::Jump { byte: jmp_byte } => {
+                        pc = jmp_byte;
 
                         if self.header.debug {
-                            println!("JMP CALL :: TO {jmp_byte} WHICH IS {:0>2x?}", bytecode.0.get(bytecode.1));
+                            println!("JMP CALL :: TO {jmp_byte} WHICH IS {:0>2x?}", cart.code.get(jmp_byte));
                         }
                     }
-                    0xe4 => {
-                        let byte_addr = bytecode.next().unwrap() as usize;
+                    Instruction::JumpVar(byte_addr) => {
+                        let byte_addr = byte_addr as usize;
 
                         let Mem::Int(jmp_byte) = self.memory[byte_addr] else {
-                            panic!("Expected int for JMP statement at byte {}", bytecode.1)
+                            panic!("Expected int for JMP statement at byte {pos}")
                         };
 
-                        bytecode.jmp(jmp_byte as usize);
+                        pc = jmp_byte as usize;
 
                         if self.header.debug {
-                            println!("VJMP CALL :: TO {jmp_byte} WHICH IS {:0>2x?} @ {byte_addr:0>2x} WHICH IS {:?}", bytecode.0.get(bytecode.1), self.memory[byte_addr]);
+                            println!("VJMP CALL :: TO {jmp_byte} WHICH IS {:0>2x?} @ {byte_addr:0>2x} WHICH IS {:?}", cart.code.get(jmp_byte as usize), self.memory[byte_addr]);
                         }
                     }
-                    0xd0 => {
-                        let keycode = Key::from_hex(bytecode.next().unwrap());
-                        let addr = bytecode.next().unwrap() as usize;
+                    Instruction::Key { key: keycode, out } => {
+                        let addr = out as usize;
 
                         if self.window.key(keycode) {
                             self.memory[addr] = Mem::Int(0x01)
@@ -597,44 +752,268 @@ impl<T: RenderBackend> Cpu<T> {
                             println!("KEY CALL :: {keycode:0>2x?} => {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
                         }
                     }
-                    0xfb => {
-                        self.window.update(self.buf);
+                    Instruction::KeyState { code, key: keycode, out } => {
+                        let addr = out as usize;
+
+                        self.memory[addr] = match code {
+                            0xd1 => Mem::Int(self.keys.down(keycode) as i64),
+                            0xd2 => Mem::Int(self.keys.pressed(keycode) as i64),
+                            0xd3 => Mem::Int(self.keys.released(keycode) as i64),
+                            _ => Mem::Int(self.keys.held(keycode) as i64),
+                        };
+
+                        if self.header.debug {
+                            let query = ["DOWN", "PRESSED", "RELEASED", "HELD"][(code - 0xd1) as usize];
+                            println!("KEY{query} CALL :: {keycode:0>2x?} => {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::ReadChar(addr) => {
+                        let addr = addr as usize;
+
+                        self.memory[addr] = match self.window.typed() {
+                            Some(c) => Mem::Int(c as i64),
+                            None => Mem::Nil,
+                        };
+
+                        if self.header.debug {
+                            println!("READCHAR CALL :: {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::ReadLine(addr) => {
+                        let addr = addr as usize;
+
+                        let mut line = ['\0'; 8];
+                        let mut len = 0;
+
+                        // Blocks until the line is entered or full.
+                        while len < line.len() && self.window.is_open() {
+                            match self.window.typed() {
+                                Some('\r' | '\n') => break,
+                                Some('\u{8}') => {
+                                    if len > 0 {
+                                        len -= 1;
+                                        line[len] = '\0';
+                                    }
+                                }
+                                Some(c) if !c.is_control() => {
+                                    line[len] = c;
+                                    len += 1;
+                                }
+                                Some(_) => {}
+                                None => {
+                                    self.window.wait_input(FRAME);
+                                    self.play_audio();
+                                }
+                            }
+                        }
+
+                        self.memory[addr] = Mem::Str(line);
 
                         if self.header.debug {
-                            println!("RENDER CALL :: RENDERED FRAME SUCCESSFULLY");
+                            println!("READLINE CALL :: {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
                         }
                     }
-                    0xfc => {
-                        let cls = bytecode.next().unwrap();
+                    Instruction::MouseX(addr) | Instruction::MouseY(addr) => {
+                        let addr = addr as usize;
+                        let is_x = matches!(inst, Instruction::MouseX(_));
+
+                        self.memory[addr] = match self.window.mouse() {
+                            Some((x, _)) if is_x => Mem::Int(x as i64),
+                            Some((_, y)) => Mem::Int(y as i64),
+                            None => Mem::Nil,
+                        };
 
-                        let cls = Colour::from_hex(cls);
+                        if self.header.debug {
+                            let axis = if is_x { "X" } else { "Y" };
+                            println!("MOUSE{axis} CALL :: {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::MouseButton { button, out } => {
+                        let addr = out as usize;
 
-                        self.buf = [cls; RES];
+                        self.memory[addr] = Mem::Int(self.window.mouse_down(button) as i64);
 
                         if self.header.debug {
-                            println!("CLS CALL :: CLEARED SCREEN TO COLOUR CODE {cls:0>2x?}");
+                            println!("MOUSEBTN CALL :: BUTTON {button} => {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
                         }
                     }
-                    inst => panic!("Unrecognized instruction: {inst:x} at byte {}", bytecode.1),
+                    Instruction::Pad { player, button, out } => {
+                        let addr = out as usize;
+
+                        let key = self.pads.key(player, button);
+
+                        self.memory[addr] = Mem::Int(self.window.key(key) as i64);
+
+                        if self.header.debug {
+                            println!("PAD CALL :: PLAYER {player} {button:?} ({key:?}) => {addr:0>2x} WHICH IS {:?}", self.memory[addr]);
+                        }
+                    }
+                    Instruction::Flush => {
+                        let dirty = self.dirty.take();
+
+                        self.flushes += 1;
+
+                        match dirty {
+                            Some(dirty) => self.compose(dirty),
+                            None => self.skipped += 1,
+                        }
+
+                        self.window.update(&self.buf, dirty);
+
+                        if let Some((frame, path)) = &self.screenshot_at && *frame == self.flushes {
+                            screenshot::save(path, &self.buf)
+                                .unwrap_or_else(|e| panic!("Failed to save screenshot to {}: {e}", path.display()));
+
+                            println!("Saved screenshot to {}", path.display());
+                            self.screenshot_at = None;
+
+                            break 'a;
+                        }
+
+                        let window = &self.window;
+                        self.keys.update(|key| window.key(key));
+
+                        match self.window.hotkey() {
+                            Some(Hotkey::SaveState) => {
+                                match self.save_state(&self.state_path, code_hash, pc) {
+                                    Ok(()) => println!("Saved state to {}", self.state_path.display()),
+                                    Err(e) => eprintln!("Failed to save state to {}: {e}", self.state_path.display()),
+                                }
+                            }
+                            Some(Hotkey::LoadState) => {
+                                let path = self.state_path.clone();
+
+                                match self.load_state(&path, code_hash) {
+                                    Ok(byte) => {
+                                        pc = byte;
+                                        println!("Loaded state from {}", path.display());
+                                    }
+                                    Err(e) => eprintln!("Failed to load state: {e}"),
+                                }
+                            }
+                            Some(Hotkey::Screenshot) => {
+                                let path = PathBuf::from(format!("{}-{}.png", self.screenshot_base.display(), self.flushes));
+
+                                match screenshot::save(&path, &self.buf) {
+                                    Ok(()) => println!("Saved screenshot to {}", path.display()),
+                                    Err(e) => eprintln!("Failed to save screenshot to {}: {e}", path.display()),
+                                }
+                            }
+                            None => {}
+                        }
+
+                        self.play_audio();
+
+                        if self.header.debug {
+                            match dirty {
+                                Some(rect) => println!(
+                                    "RENDER CALL :: RENDERED FRAME SUCCESSFULLY, ({}, {}) TO ({}, {}) WAS DIRTY ({} ROWS, {} PIXELS)",
+                                    rect.x0,
+                                    rect.y0,
+                                    rect.x1,
+                                    rect.y1,
+                                    rect.rows().len(),
+                                    rect.area()
+                                ),
+                                None => println!(
+                                    "RENDER CALL :: NOTHING CHANGED, SKIPPED FRAME ({} OF {} FRAMES SKIPPED)",
+                                    self.skipped, self.flushes
+                                ),
+                            }
+                        }
+                    }
+                    Instruction::Clear(cls) => {
+                        self.layers[self.layer].fill(cls);
+                        self.mark(Rect::FULL);
+
+                        if self.header.debug {
+                            println!("CLS CALL :: CLEARED LAYER {} TO COLOUR CODE {cls:0>2x?}", self.layer);
+                        }
+                    }
+                    Instruction::Invalid(ref message) => panic!("{message}"),
                 }
 
                 // Must be used when using FLTK, otherwise
                 // the frame will not render.
                 self.window.fltk_up();
 
+                steps += 1;
+
+                if steps % AUDIO_STEPS == 0 {
+                    self.play_audio();
+                }
+
                 if self.header.debug {
-                    println!("INFO :: BYTE NO. {} (0x{:0>8x})", bytecode.1, bytecode.1);
+                    println!("INFO :: BYTE NO. {pc} (0x{pc:0>8x})");
                 }
             }
 
             if !self.header.repeat {
                 if self.header.keep_open {
-                    while self.window.is_open() {}
+                    // Music keeps playing until the window is closed.
+                    while !self.window.wait_closed(FRAME) {
+                        self.play_audio();
+                    }
                 }
                 break;
             }
 
-            bytecode.jmp(0);
+            pc = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Headless;
+
+    /// Runs `code` as a legacy cartridge with an empty header until the first
+    /// flush has been shown.
+    fn run(code: &[u8]) -> Cpu<Headless> {
+        let mut cpu = Cpu::new(Headless::new(1));
+        cpu.run([&[0x00], code].concat());
+        cpu
+    }
+
+    fn var(value: i64, addr: u8) -> Vec<u8> {
+        [&[0xa1, 0xe0][..], &value.to_le_bytes(), &[addr]].concat()
+    }
+
+    #[test]
+    fn mark_unions_with_what_is_already_dirty() {
+        let mut cpu = Cpu::new(Headless::new(0));
+        cpu.dirty = None;
+
+        cpu.mark(Rect::new(10, 20, 8, 8));
+        assert_eq!(cpu.dirty, Some(Rect::new(10, 20, 8, 8)));
+
+        cpu.mark(Rect::pixel(0));
+        assert_eq!(cpu.dirty, Some(Rect::new(0, 0, 18, 28)));
+    }
+
+    #[test]
+    fn pixel_past_the_right_edge() {
+        // Red at x = 255, which is the start of the next row.
+        let cpu = run(&[0x01, 0xff, 0x02, 0x1a, 0xfb]);
+
+        assert_eq!(cpu.window.frame[3 * WIDTH], Colour::Red);
+    }
+
+    #[test]
+    fn sprite_past_the_left_edge() {
+        // A red sprite at (-1, 1), with rows read from addresses 2-9.
+        let row = [&[0xa1, 0x8a][..], &[0x1a; 8]].concat();
+        let rows: Vec<u8> = (2..10).flat_map(|addr| [&row[..], &[addr]].concat()).collect();
+        let code = [&var(-1, 0)[..], &var(1, 1), &rows, &[0x03, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 0xfb]].concat();
+
+        let cpu = run(&code);
+
+        // The first column spills onto the end of the row above.
+        for y in 1..9 {
+            assert_eq!(cpu.window.frame[y * WIDTH - 1], Colour::Red);
+            assert_eq!(cpu.window.frame[y * WIDTH + 6], Colour::Red);
+            assert_eq!(cpu.window.frame[y * WIDTH + 7], Colour::Green);
         }
     }
 }
@@ -0,0 +1,516 @@
+use crate::{color::Colour, cpu::Mem, key::Key, pad::Button};
+
+/// Kinds of operand an instruction can take, as laid out in the bytecode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    /// Memory address (`0x00`-`0xfe`).
+    Addr,
+    /// First of eight consecutive memory addresses holding sprite rows.
+    Rows,
+    /// Any byte, such as a screen position or volume.
+    Byte,
+    Colour,
+    Key,
+    /// Variable type code.
+    Type,
+    /// Eight data bytes.
+    Data,
+    /// Eight byte jump target.
+    Jump,
+    /// Tile ID in the sprite sheet.
+    Tile,
+    /// Tile ID in the sprite sheet, or `0xff` to clear a background tile.
+    MapTile,
+    Layer,
+    Channel,
+    Pattern,
+    /// Index into an eight item array.
+    Index,
+    Slot,
+    Player,
+    Button,
+    MouseButton,
+}
+
+impl Operand {
+    pub fn size(self) -> usize {
+        match self {
+            Operand::Data | Operand::Jump => 8,
+            _ => 1,
+        }
+    }
+}
+
+/// Name and operand layout of an instruction.
+#[derive(Debug, Copy, Clone)]
+pub struct Op {
+    pub name: &'static str,
+    pub operands: &'static [Operand],
+}
+
+impl Op {
+    /// Size of the whole instruction in bytes, including the opcode.
+    pub fn size(&self) -> usize {
+        1 + self.operands.iter().map(|op| op.size()).sum::<usize>()
+    }
+
+    /// Splits the bytes after the opcode up by operand, along with the
+    /// offset of each from the opcode.
+    pub fn split<'a>(&self, args: &'a [u8]) -> impl Iterator<Item = (usize, Operand, &'a [u8])> + 'a {
+        let mut offset = 1;
+
+        self.operands.iter().map(move |operand| {
+            let start = offset;
+            offset += operand.size();
+
+            (start, *operand, &args[start - 1..offset - 1])
+        })
+    }
+}
+
+/// Looks up the instruction for an opcode, as executed by `Cpu::run`.
+pub fn op(code: u8) -> Option<Op> {
+    use Operand::*;
+
+    let (name, operands): (_, &'static [Operand]) = match code {
+        0x00 => ("NOOP", &[]),
+        0x01 => ("CPIX", &[Byte, Byte, Colour]),
+        0x02 => ("PIX", &[Addr, Addr, Colour]),
+        0x03 => ("SPR", &[Addr, Addr, Addr, Addr, Addr, Addr, Addr, Addr, Addr, Addr]),
+        0x04 => ("SPRI", &[Tile, Addr, Addr]),
+        0x05 => ("TILE", &[Addr, Addr, MapTile]),
+        0x06 => ("SCROLLX", &[Addr]),
+        0x07 => ("SCROLLY", &[Addr]),
+        0x08 => ("LAYER", &[Layer]),
+        0x09 => ("COLLIDE", &[Addr, Addr, Addr, Addr, Addr]),
+        0x0a => ("PCOLLIDE", &[Addr, Addr, Rows, Addr, Addr, Rows, Addr]),
+        0x0b => ("PEEKPIX", &[Addr, Addr, Addr]),
+        0x40 => ("TONE", &[Channel, Addr, Addr, Byte]),
+        0x41 => ("PLAYMUSIC", &[Pattern]),
+        0x42 => ("STOPMUSIC", &[]),
+        0xa1 => ("VAR", &[Type, Data, Addr]),
+        0xa2 => ("LET", &[Type, Data, Addr]),
+        0xa3 => ("ARRW", &[Addr, Index, Byte]),
+        0xa4 => ("PSTORE", &[Slot, Addr]),
+        0xa5 => ("PLOAD", &[Slot, Addr]),
+        0xb0 => ("NOT", &[Addr, Addr]),
+        0xb1 => ("GT", &[Addr, Addr, Addr]),
+        0xb2 => ("LT", &[Addr, Addr, Addr]),
+        0xd0 => ("KEY", &[Key, Addr]),
+        0xd1 => ("KEYDOWN", &[Key, Addr]),
+        0xd2 => ("KEYPRESSED", &[Key, Addr]),
+        0xd3 => ("KEYRELEASED", &[Key, Addr]),
+        0xd4 => ("KEYHELD", &[Key, Addr]),
+        0xd6 => ("READCHAR", &[Addr]),
+        0xd7 => ("READLINE", &[Addr]),
+        0xd8 => ("MOUSEX", &[Addr]),
+        0xd9 => ("MOUSEY", &[Addr]),
+        0xda => ("MOUSEBTN", &[MouseButton, Addr]),
+        0xdb => ("PAD", &[Player, Button, Addr]),
+        0xe1 => ("TJMP", &[Addr, Jump]),
+        0xe2 => ("FJMP", &[Addr, Jump]),
+        0xe3 => ("JMP", &[Jump]),
+        0xe4 => ("VJMP", &[Addr]),
+        0xf0 => ("FDIV", &[Addr, Addr, Addr]),
+        0xf1 => ("FSUB", &[Addr, Addr, Addr]),
+        0xf2 => ("FADD", &[Addr, Addr, Addr]),
+        0xf3 => ("FMUL", &[Addr, Addr, Addr]),
+        0xf4 => ("DIV", &[Addr, Addr, Addr]),
+        0xf5 => ("SUB", &[Addr, Addr, Addr]),
+        0xf6 => ("ADD", &[Addr, Addr, Addr]),
+        0xf7 => ("MUL", &[Addr, Addr, Addr]),
+        0xfb => ("FLUSH", &[]),
+        0xfc => ("CLS", &[Colour]),
+        _ => return None,
+    };
+
+    Some(Op { name, operands })
+}
+
+/// One instruction's worth of bytecode, as split up by `walk`.
+#[derive(Debug, Copy, Clone)]
+pub enum Step<'a> {
+    /// A whole instruction, with the bytes of its operands.
+    Inst { code: u8, op: Op, args: &'a [u8] },
+    /// An opcode that isn't in the instruction set.
+    Unknown(u8),
+    /// An instruction cut off by the end of the code, with the number of
+    /// bytes that were left.
+    Truncated { op: Op, left: usize },
+}
+
+/// Splits off the instruction starting at a byte offset, along with the
+/// offset decoding carries on from after it. Bytes that can't be decoded
+/// are split off one at a time, so that data in between instructions can
+/// still be jumped over.
+pub fn step(code: &[u8], start: usize) -> Option<(Step<'_>, usize)> {
+    let byte = *code.get(start)?;
+
+    Some(match op(byte) {
+        None => (Step::Unknown(byte), start + 1),
+        Some(op) if start + op.size() > code.len() => (
+            Step::Truncated {
+                op,
+                left: code.len() - start,
+            },
+            start + 1,
+        ),
+        Some(op) => (
+            Step::Inst {
+                code: byte,
+                op,
+                args: &code[start + 1..start + op.size()],
+            },
+            start + op.size(),
+        ),
+    })
+}
+
+/// Splits the code into instructions one after another from a byte offset,
+/// along with the offset of each.
+pub fn walk(code: &[u8], start: usize) -> impl Iterator<Item = (usize, Step<'_>)> + '_ {
+    let mut pos = start;
+
+    std::iter::from_fn(move || {
+        let start = pos;
+        let (step, next) = step(code, start)?;
+
+        pos = next;
+
+        Some((start, step))
+    })
+}
+
+/// An instruction with its operands decoded, as executed by `Cpu::run`.
+/// Addresses are left as `u8`s.
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    Noop,
+    ConstPixel { x: u8, y: u8, clr: Colour },
+    Pixel { x: u8, y: u8, clr: Colour },
+    Sprite { rows: [u8; 8], x: u8, y: u8 },
+    SpriteTile { tile: u8, x: u8, y: u8 },
+    Tile { col: u8, row: u8, tile: u8 },
+    ScrollX(u8),
+    ScrollY(u8),
+    Layer(u8),
+    Collide { ax: u8, ay: u8, bx: u8, by: u8, out: u8 },
+    PixelCollide { ax: u8, ay: u8, a_rows: u8, bx: u8, by: u8, b_rows: u8, out: u8 },
+    Peek { x: u8, y: u8, out: u8 },
+    Tone { channel: u8, freq: u8, duration: u8, volume: u8 },
+    PlayMusic(u8),
+    StopMusic,
+    /// `0xf0`-`0xf7`, which all take two operands and an output address.
+    Arith { code: u8, lhs: u8, rhs: u8, out: u8 },
+    Not { addr: u8, out: u8 },
+    Gt { lhs: u8, rhs: u8, out: u8 },
+    Lt { lhs: u8, rhs: u8, out: u8 },
+    /// The value is built from the type and data when decoding, which are
+    /// kept for debug output.
+    Var { ty: u8, data: [u8; 8], value: Mem, addr: u8 },
+    Let { ty: u8, data: [u8; 8], value: Mem, addr: u8 },
+    ArrayWrite { addr: u8, idx: usize, item: u8 },
+    Store { slot: u8, addr: u8 },
+    Load { slot: u8, addr: u8 },
+    /// Jumps hold the byte offset to carry on from, which can be anywhere
+    /// in the code.
+    JumpIf { cond: u8, byte: usize },
+    JumpIfNot { cond: u8, byte: usize },
+    Jump { byte: usize },
+    JumpVar(u8),
+    Key { key: Key, out: u8 },
+    /// `0xd1`-`0xd4`, which query the key's state since the last flush.
+    KeyState { code: u8, key: Key, out: u8 },
+    ReadChar(u8),
+    ReadLine(u8),
+    MouseX(u8),
+    MouseY(u8),
+    MouseButton { button: u8, out: u8 },
+    Pad { player: u8, button: Button, out: u8 },
+    Flush,
+    Clear(Colour),
+    /// Panics with the message when run. Bad operands, unknown opcodes and
+    /// truncated instructions only fail once they're reached, as they
+    /// would if the bytecode was read as it ran.
+    Invalid(String),
+}
+
+/// A whole program, decoded ahead of time.
+pub struct Program<'a> {
+    code: &'a [u8],
+    /// The instruction starting at each byte offset, and the offset of the
+    /// one after it, for every offset that has been decoded so far.
+    insts: Vec<Option<(Instruction, usize)>>,
+}
+
+impl<'a> Program<'a> {
+    /// Decodes the code one instruction after another from the start.
+    /// Unknown opcodes and truncated instructions are decoded as
+    /// `Instruction::Invalid`, one byte each.
+    ///
+    /// Jumps can land on any byte, including the middle of an instruction
+    /// decoded here, so other offsets are decoded when they are first run.
+    pub fn decode(code: &'a [u8]) -> Self {
+        let mut program = Program {
+            code,
+            insts: vec![None; code.len()],
+        };
+
+        let mut pos = 0;
+
+        while let Some((_, next)) = program.get(pos) {
+            pos = *next;
+        }
+
+        program
+    }
+
+    /// The instruction starting at a byte offset, and the offset of the one
+    /// after it, decoding it first if needs be. There is nothing at or past
+    /// the end of the code, which ends the program.
+    pub fn get(&mut self, pos: usize) -> Option<&(Instruction, usize)> {
+        let code = self.code;
+        let inst = self.insts.get_mut(pos)?;
+
+        Some(inst.get_or_insert_with(|| {
+            let (step, next) = step(code, pos).unwrap();
+
+            let inst = match step {
+                Step::Inst { code, args, .. } => decode_inst(code, args).unwrap_or_else(Instruction::Invalid),
+                Step::Unknown(byte) => Instruction::Invalid(format!("Unrecognized instruction: {byte:x} at byte {}", pos + 1)),
+                Step::Truncated { .. } => Instruction::Invalid(format!("Unexpected EOF at byte {}", code.len())),
+            };
+
+            (inst, next)
+        }))
+    }
+
+    /// Byte offset of every instruction decoded so far, in order.
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.insts
+            .iter()
+            .enumerate()
+            .filter(|(_, inst)| inst.is_some())
+            .map(|(pos, _)| pos)
+    }
+}
+
+/// Decodes the operands of an instruction, returning the message it should
+/// panic with if any of them are invalid.
+fn decode_inst(code: u8, args: &[u8]) -> Result<Instruction, String> {
+    let colour = |num: u8| Colour::try_from_hex(num).ok_or_else(|| format!("Unknown colour hexcode: {num:0>2x}!"));
+    let key = |hex: u8| Key::try_from_hex(hex).ok_or_else(|| format!("Cannot convert {hex:x} to keycode"));
+    let jump = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap()) as usize;
+
+    let value = |ty: u8, data: [u8; 8]| {
+        Ok(match ty {
+            0xe0 => Mem::Int(i64::from_le_bytes(data)),
+            0xf0 => Mem::Float(f64::from_le_bytes(data)),
+            0xab => Mem::Str(data.map(|e| e as char)),
+            0x8a => Mem::ByteArr(data),
+            any => return Err(format!("Unknown type: {any:x}")),
+        })
+    };
+
+    Ok(match code {
+        0x00 => Instruction::Noop,
+        0x01 => Instruction::ConstPixel {
+            x: args[0],
+            y: args[1],
+            clr: colour(args[2])?,
+        },
+        0x02 => Instruction::Pixel {
+            x: args[0],
+            y: args[1],
+            clr: colour(args[2])?,
+        },
+        0x03 => Instruction::Sprite {
+            rows: args[..8].try_into().unwrap(),
+            x: args[8],
+            y: args[9],
+        },
+        0x04 => Instruction::SpriteTile {
+            tile: args[0],
+            x: args[1],
+            y: args[2],
+        },
+        0x05 => Instruction::Tile {
+            col: args[0],
+            row: args[1],
+            tile: args[2],
+        },
+        0x06 => Instruction::ScrollX(args[0]),
+        0x07 => Instruction::ScrollY(args[0]),
+        0x08 => Instruction::Layer(args[0]),
+        0x09 => Instruction::Collide {
+            ax: args[0],
+            ay: args[1],
+            bx: args[2],
+            by: args[3],
+            out: args[4],
+        },
+        0x0a => Instruction::PixelCollide {
+            ax: args[0],
+            ay: args[1],
+            a_rows: args[2],
+            bx: args[3],
+            by: args[4],
+            b_rows: args[5],
+            out: args[6],
+        },
+        0x0b => Instruction::Peek {
+            x: args[0],
+            y: args[1],
+            out: args[2],
+        },
+        0x40 => Instruction::Tone {
+            channel: args[0],
+            freq: args[1],
+            duration: args[2],
+            volume: args[3],
+        },
+        0x41 => Instruction::PlayMusic(args[0]),
+        0x42 => Instruction::StopMusic,
+        0xa1 | 0xa2 => {
+            let ty = args[0];
+            let data = args[1..9].try_into().unwrap();
+            let (value, addr) = (value(ty, data)?, args[9]);
+
+            if code == 0xa1 {
+                Instruction::Var { ty, data, value, addr }
+            } else {
+                Instruction::Let { ty, data, value, addr }
+            }
+        }
+        0xa3 => Instruction::ArrayWrite {
+            addr: args[0],
+            idx: match args[1] as usize {
+                idx @ 0..=7 => idx,
+                _ => return Err("Array index out of bounds. (Zero based indexing!)".to_string()),
+            },
+            item: args[2],
+        },
+        0xa4 => Instruction::Store { slot: args[0], addr: args[1] },
+        0xa5 => Instruction::Load { slot: args[0], addr: args[1] },
+        0xb0 => Instruction::Not { addr: args[0], out: args[1] },
+        0xb1 => Instruction::Gt {
+            lhs: args[0],
+            rhs: args[1],
+            out: args[2],
+        },
+        0xb2 => Instruction::Lt {
+            lhs: args[0],
+            rhs: args[1],
+            out: args[2],
+        },
+        0xd0 => Instruction::Key {
+            key: key(args[0])?,
+            out: args[1],
+        },
+        0xd1..=0xd4 => Instruction::KeyState {
+            code,
+            key: key(args[0])?,
+            out: args[1],
+        },
+        0xd6 => Instruction::ReadChar(args[0]),
+        0xd7 => Instruction::ReadLine(args[0]),
+        0xd8 => Instruction::MouseX(args[0]),
+        0xd9 => Instruction::MouseY(args[0]),
+        0xda => Instruction::MouseButton {
+            button: args[0],
+            out: args[1],
+        },
+        0xdb => Instruction::Pad {
+            player: args[0],
+            button: Button::try_from_hex(args[1]).ok_or_else(|| format!("Cannot convert {:x} to button code", args[1]))?,
+            out: args[2],
+        },
+        0xe1 => Instruction::JumpIf {
+            cond: args[0],
+            byte: jump(&args[1..]),
+        },
+        0xe2 => Instruction::JumpIfNot {
+            cond: args[0],
+            byte: jump(&args[1..]),
+        },
+        0xe3 => Instruction::Jump { byte: jump(args) },
+        0xe4 => Instruction::JumpVar(args[0]),
+        0xf0..=0xf7 => Instruction::Arith {
+            code,
+            lhs: args[0],
+            rhs: args[1],
+            out: args[2],
+        },
+        0xfb => Instruction::Flush,
+        0xfc => Instruction::Clear(colour(args[0])?),
+        inst => return Err(format!("Unrecognized instruction: {inst:x}")),
+    })
+}
+
+/// Bytecode for a jump (`0xe3`) to `target`, for building programs in tests.
+#[cfg(test)]
+pub fn jmp(target: u64) -> Vec<u8> {
+    [&[0xe3], &target.to_le_bytes()[..]].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_to_offset() {
+        let code = [&[0xfc, 0x00, 0xfb][..], &jmp(2)].concat();
+        let mut program = Program::decode(&code);
+
+        assert_eq!(program.offsets().collect::<Vec<_>>(), [0, 2, 3]);
+
+        assert!(matches!(program.get(0), Some((Instruction::Clear(Colour::Black), 2))));
+        assert!(matches!(program.get(2), Some((Instruction::Flush, 3))));
+        assert!(matches!(program.get(3), Some((Instruction::Jump { byte: 2 }, 12))));
+    }
+
+    #[test]
+    fn jump_past_end() {
+        let code = jmp(100);
+        let mut program = Program::decode(&code);
+
+        assert!(matches!(program.get(0), Some((Instruction::Jump { byte: 100 }, 9))));
+
+        // Jumping to or past the end finishes the program.
+        assert!(program.get(9).is_none());
+        assert!(program.get(100).is_none());
+    }
+
+    #[test]
+    fn jump_into_middle_of_instruction() {
+        // Byte 10 is the colour of the CLS at byte 9, which isn't a
+        // colour, but is a FLUSH when jumped to.
+        let code = [&jmp(10)[..], &[0xfc, 0xfb]].concat();
+        let mut program = Program::decode(&code);
+
+        assert_eq!(program.offsets().collect::<Vec<_>>(), [0, 9]);
+        assert!(matches!(program.get(9), Some((Instruction::Invalid(_), 11))));
+
+        assert!(matches!(program.get(10), Some((Instruction::Flush, 11))));
+        assert_eq!(program.offsets().collect::<Vec<_>>(), [0, 9, 10]);
+    }
+
+    #[test]
+    fn unknown_and_truncated_instructions() {
+        let code = [0xff, 0xfb, 0xe3, 0x01];
+        let mut program = Program::decode(&code);
+
+        // Decoding carries on from the byte after anything invalid.
+        assert_eq!(program.offsets().collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+        let Some((Instruction::Invalid(message), 1)) = program.get(0) else {
+            panic!("expected an invalid instruction");
+        };
+        assert_eq!(message, "Unrecognized instruction: ff at byte 1");
+
+        let Some((Instruction::Invalid(message), 3)) = program.get(2) else {
+            panic!("expected an invalid instruction");
+        };
+        assert_eq!(message, "Unexpected EOF at byte 4");
+    }
+}
@@ -11,19 +11,59 @@ pub enum Key {
     Z = 0x06,
     X = 0x07,
     C = 0x08,
+    B = 0x09,
+    F = 0x0a,
+    G = 0x0b,
+    H = 0x0c,
+    I = 0x0d,
+    J = 0x0e,
+    K = 0x0f,
+    L = 0x10,
+    M = 0x11,
+    N = 0x12,
+    O = 0x13,
+    P = 0x14,
+    R = 0x15,
+    T = 0x16,
+    U = 0x17,
+    V = 0x18,
+    Y = 0x19,
+    Num0 = 0x30,
+    Num1 = 0x31,
+    Num2 = 0x32,
+    Num3 = 0x33,
+    Num4 = 0x34,
+    Num5 = 0x35,
+    Num6 = 0x36,
+    Num7 = 0x37,
+    Num8 = 0x38,
+    Num9 = 0x39,
     Up = 0xd1,
     Dwn = 0xd2,
     Lft = 0xd3,
     Rght = 0xd4,
     Spc = 0xf0,
     LCtrl = 0xc1,
-    RCtrl = 0xc2
+    RCtrl = 0xc2,
+    LShift = 0xc3,
+    RShift = 0xc4,
+    Enter = 0xe0,
+    Esc = 0xe1,
+    Tab = 0xe2,
+    Bksp = 0xe3
 }
 
+use fltk::enums::Key as FKey;
 use minifb::Key as MKey;
 use Key::*;
 
 impl Key {
+    pub const ALL: [Key; 49] = [
+        Q, W, E, A, S, D, Z, X, C, B, F, G, H, I, J, K, L, M, N, O, P, R, T, U, V, Y,
+        Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+        Up, Dwn, Lft, Rght, Spc, LCtrl, RCtrl, LShift, RShift, Enter, Esc, Tab, Bksp,
+    ];
+
     pub fn to_fb_key(&self) -> MKey {
         match self {
             Q => MKey::Q,
@@ -35,6 +75,33 @@ impl Key {
             Z => MKey::Z,
             X => MKey::X,
             C => MKey::C,
+            B => MKey::B,
+            F => MKey::F,
+            G => MKey::G,
+            H => MKey::H,
+            I => MKey::I,
+            J => MKey::J,
+            K => MKey::K,
+            L => MKey::L,
+            M => MKey::M,
+            N => MKey::N,
+            O => MKey::O,
+            P => MKey::P,
+            R => MKey::R,
+            T => MKey::T,
+            U => MKey::U,
+            V => MKey::V,
+            Y => MKey::Y,
+            Num0 => MKey::Key0,
+            Num1 => MKey::Key1,
+            Num2 => MKey::Key2,
+            Num3 => MKey::Key3,
+            Num4 => MKey::Key4,
+            Num5 => MKey::Key5,
+            Num6 => MKey::Key6,
+            Num7 => MKey::Key7,
+            Num8 => MKey::Key8,
+            Num9 => MKey::Key9,
             Up => MKey::Up,
             Dwn => MKey::Down,
             Lft => MKey::Left,
@@ -42,6 +109,66 @@ impl Key {
             Spc => MKey::Space,
             LCtrl => MKey::LeftCtrl,
             RCtrl => MKey::RightCtrl,
+            LShift => MKey::LeftShift,
+            RShift => MKey::RightShift,
+            Enter => MKey::Enter,
+            Esc => MKey::Escape,
+            Tab => MKey::Tab,
+            Bksp => MKey::Backspace,
+        }
+    }
+
+    pub fn to_fltk_key(&self) -> FKey {
+        match self {
+            Q => FKey::from_char('q'),
+            W => FKey::from_char('w'),
+            E => FKey::from_char('e'),
+            A => FKey::from_char('a'),
+            S => FKey::from_char('s'),
+            D => FKey::from_char('d'),
+            Z => FKey::from_char('z'),
+            X => FKey::from_char('x'),
+            C => FKey::from_char('c'),
+            B => FKey::from_char('b'),
+            F => FKey::from_char('f'),
+            G => FKey::from_char('g'),
+            H => FKey::from_char('h'),
+            I => FKey::from_char('i'),
+            J => FKey::from_char('j'),
+            K => FKey::from_char('k'),
+            L => FKey::from_char('l'),
+            M => FKey::from_char('m'),
+            N => FKey::from_char('n'),
+            O => FKey::from_char('o'),
+            P => FKey::from_char('p'),
+            R => FKey::from_char('r'),
+            T => FKey::from_char('t'),
+            U => FKey::from_char('u'),
+            V => FKey::from_char('v'),
+            Y => FKey::from_char('y'),
+            Num0 => FKey::from_char('0'),
+            Num1 => FKey::from_char('1'),
+            Num2 => FKey::from_char('2'),
+            Num3 => FKey::from_char('3'),
+            Num4 => FKey::from_char('4'),
+            Num5 => FKey::from_char('5'),
+            Num6 => FKey::from_char('6'),
+            Num7 => FKey::from_char('7'),
+            Num8 => FKey::from_char('8'),
+            Num9 => FKey::from_char('9'),
+            Up => FKey::Up,
+            Dwn => FKey::Down,
+            Lft => FKey::Left,
+            Rght => FKey::Right,
+            Spc => FKey::from_char(' '),
+            LCtrl => FKey::ControlL,
+            RCtrl => FKey::ControlR,
+            LShift => FKey::ShiftL,
+            RShift => FKey::ShiftR,
+            Enter => FKey::Enter,
+            Esc => FKey::Escape,
+            Tab => FKey::Tab,
+            Bksp => FKey::BackSpace,
         }
     }
 
@@ -56,25 +183,58 @@ impl Key {
             "Z" => Key::Z,
             "X" => Key::X,
             "C" => Key::C,
+            "B" => Key::B,
+            "F" => Key::F,
+            "G" => Key::G,
+            "H" => Key::H,
+            "I" => Key::I,
+            "J" => Key::J,
+            "K" => Key::K,
+            "L" => Key::L,
+            "M" => Key::M,
+            "N" => Key::N,
+            "O" => Key::O,
+            "P" => Key::P,
+            "R" => Key::R,
+            "T" => Key::T,
+            "U" => Key::U,
+            "V" => Key::V,
+            "Y" => Key::Y,
+            "0" => Key::Num0,
+            "1" => Key::Num1,
+            "2" => Key::Num2,
+            "3" => Key::Num3,
+            "4" => Key::Num4,
+            "5" => Key::Num5,
+            "6" => Key::Num6,
+            "7" => Key::Num7,
+            "8" => Key::Num8,
+            "9" => Key::Num9,
             "UP" => Key::Up,
             "DWN" => Key::Dwn,
             "LFT" => Key::Lft,
-            "RGHT" => Key::Rght,
+            "RGHT" | "RGT" => Key::Rght,
             "SPC" | " " => Key::Spc,
             "LCTRL" => Key::LCtrl,
             "RCTRL" => Key::RCtrl,
+            "LSHIFT" => Key::LShift,
+            "RSHIFT" => Key::RShift,
+            "ENTER" => Key::Enter,
+            "ESC" => Key::Esc,
+            "TAB" => Key::Tab,
+            "BKSP" => Key::Bksp,
             _ => return None,
         })
     }
 
-    pub fn from_hex(hex: u8) -> Key {
+    pub fn try_from_hex(hex: u8) -> Option<Key> {
         macro_rules! hexcode {
             ($($ident: ident = $expr: expr),+) => {
               match hex {
               $(
-                $expr => $ident
+                $expr => Some($ident)
               ),*,
-              any => panic!("Cannot convert {any:x} to keycode")
+              _ => None
             }
           }
         }
@@ -89,13 +249,132 @@ impl Key {
             Z    = 0x06,
             X    = 0x07,
             C    = 0x08,
+            B    = 0x09,
+            F    = 0x0a,
+            G    = 0x0b,
+            H    = 0x0c,
+            I    = 0x0d,
+            J    = 0x0e,
+            K    = 0x0f,
+            L    = 0x10,
+            M    = 0x11,
+            N    = 0x12,
+            O    = 0x13,
+            P    = 0x14,
+            R    = 0x15,
+            T    = 0x16,
+            U    = 0x17,
+            V    = 0x18,
+            Y    = 0x19,
+            Num0 = 0x30,
+            Num1 = 0x31,
+            Num2 = 0x32,
+            Num3 = 0x33,
+            Num4 = 0x34,
+            Num5 = 0x35,
+            Num6 = 0x36,
+            Num7 = 0x37,
+            Num8 = 0x38,
+            Num9 = 0x39,
             Up   = 0xd1,
             Dwn  = 0xd2,
             Lft  = 0xd3,
             Rght = 0xd4,
             Spc  = 0xf0,
             LCtrl = 0xc1,
-            RCtrl = 0xc2
+            RCtrl = 0xc2,
+            LShift = 0xc3,
+            RShift = 0xc4,
+            Enter = 0xe0,
+            Esc  = 0xe1,
+            Tab  = 0xe2,
+            Bksp = 0xe3
+        }
+    }
+}
+
+/// Keyboard state sampled once per frame, so that presses and releases
+/// can be told apart from keys that are simply held.
+pub struct KeyState {
+    /// Number of frames each key has been held for, indexed by keycode.
+    held: [u32; 256],
+    /// Keys that were let go of during the last frame.
+    released: [bool; 256],
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        KeyState {
+            held: [0; 256],
+            released: [false; 256],
         }
     }
 }
+
+impl KeyState {
+    /// Samples every key with `is_down` and starts a new frame.
+    pub fn update(&mut self, is_down: impl Fn(Key) -> bool) {
+        for key in Key::ALL {
+            let i = key as usize;
+
+            if is_down(key) {
+                self.held[i] += 1;
+                self.released[i] = false;
+            } else {
+                self.released[i] = self.held[i] > 0;
+                self.held[i] = 0;
+            }
+        }
+    }
+
+    pub fn down(&self, key: Key) -> bool {
+        self.held[key as usize] > 0
+    }
+
+    /// Whether the key went down this frame.
+    pub fn pressed(&self, key: Key) -> bool {
+        self.held[key as usize] == 1
+    }
+
+    /// Whether the key was let go of this frame.
+    pub fn released(&self, key: Key) -> bool {
+        self.released[key as usize]
+    }
+
+    pub fn held(&self, key: Key) -> u32 {
+        self.held[key as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn press_hold_and_release() {
+        let mut keys = KeyState::default();
+
+        // Nothing is down before the first frame.
+        assert!(!keys.down(Spc));
+
+        keys.update(|key| key == Spc);
+        assert!(keys.down(Spc) && keys.pressed(Spc) && !keys.released(Spc));
+        assert_eq!(keys.held(Spc), 1);
+
+        keys.update(|key| key == Spc);
+        keys.update(|key| key == Spc);
+        assert!(keys.down(Spc) && !keys.pressed(Spc) && !keys.released(Spc));
+        assert_eq!(keys.held(Spc), 3);
+
+        keys.update(|_| false);
+        assert!(!keys.down(Spc) && !keys.pressed(Spc) && keys.released(Spc));
+        assert_eq!(keys.held(Spc), 0);
+
+        // Released only lasts for the frame the key was let go in.
+        keys.update(|_| false);
+        assert!(!keys.released(Spc));
+
+        // Other keys are left alone.
+        assert!(!keys.down(Enter) && !keys.released(Enter));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{color::Colour, key::Key, render::{Hotkey, Rect, RenderBackend}, RES};
+
+use std::{fs::read_to_string, path::Path, time::Duration};
+
+/// Config file that is loaded at startup if no other keymap is given.
+pub const DEFAULT_PATH: &str = "atc_keys.cfg";
+
+/// Maps each virtual key to the physical keys that press it.
+/// Keys that are not in the map are pressed by their own key.
+pub struct KeyMap(Vec<Option<Vec<Key>>>);
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap(vec![None; 256])
+    }
+}
+
+impl KeyMap {
+    /// Parses lines of the form `Z = Y` or `UP = W, UP`, where the left hand
+    /// side is the key the cartridge asks for and the right hand side lists
+    /// the keys the player presses. Anything after a `#` is ignored.
+    pub fn parse(config: &str) -> Self {
+        let mut map = KeyMap::default();
+
+        for (line_no, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((virt, physical)) = line.split_once('=') else {
+                panic!("Expected `KEY = KEY, ...` on line {} of keymap", line_no + 1)
+            };
+
+            let parse_key = |name: &str| {
+                Key::from_str(name.trim())
+                    .unwrap_or_else(|| panic!("Unknown key {:?} on line {} of keymap", name.trim(), line_no + 1))
+            };
+
+            let virt = parse_key(virt);
+            let physical = physical.split(',').map(parse_key).collect();
+
+            map.0[virt as usize] = Some(physical);
+        }
+
+        map
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+
+        let config = read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read keymap {}: {e}", path.display()));
+
+        Self::parse(&config)
+    }
+
+    /// Whether the virtual key is pressed, according to `is_down` for
+    /// physical keys.
+    pub fn pressed(&self, key: Key, is_down: impl Fn(Key) -> bool) -> bool {
+        match &self.0[key as usize] {
+            Some(physical) => physical.iter().any(|key| is_down(*key)),
+            None => is_down(key),
+        }
+    }
+}
+
+/// Wraps a backend, translating key queries through a `KeyMap`.
+pub struct Remapped<T: RenderBackend>(T, KeyMap);
+
+impl<T: RenderBackend> Remapped<T> {
+    pub fn new(inner: T, keymap: KeyMap) -> Self {
+        Self(inner, keymap)
+    }
+}
+
+impl<T: RenderBackend> RenderBackend for Remapped<T> {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        self.0.update(buf, dirty)
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.is_open()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.0.set_title(title)
+    }
+
+    fn key(&self, key: Key) -> bool {
+        self.1.pressed(key, |key| self.0.key(key))
+    }
+
+    fn mouse(&self) -> Option<(usize, usize)> {
+        self.0.mouse()
+    }
+
+    fn mouse_down(&self, button: u8) -> bool {
+        self.0.mouse_down(button)
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        self.0.typed()
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.0.hotkey()
+    }
+
+    fn fltk_up(&self) {
+        self.0.fltk_up()
+    }
+
+    fn wait_input(&self, timeout: Duration) {
+        self.0.wait_input(timeout)
+    }
+
+    fn wait_closed(&self, timeout: Duration) -> bool {
+        self.0.wait_closed(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_mapping() {
+        let map = KeyMap::parse("# AZERTY\nZ = W\n\nUP = Z, up  # either\n");
+
+        assert!(map.pressed(Key::Z, |key| key == Key::W));
+        assert!(!map.pressed(Key::Z, |key| key == Key::Z));
+        assert!(map.pressed(Key::Up, |key| key == Key::Z));
+        assert!(map.pressed(Key::Up, |key| key == Key::Up));
+
+        // Keys that aren't mapped are pressed by themselves.
+        assert!(map.pressed(Key::Spc, |key| key == Key::Spc));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown key \"Ü\" on line 2 of keymap")]
+    fn unknown_key() {
+        KeyMap::parse("Z = W\nZ = Ü");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected `KEY = KEY, ...` on line 1 of keymap")]
+    fn missing_equals() {
+        KeyMap::parse("Z W");
+    }
+
+    #[test]
+    fn duplicate_entry() {
+        // The last mapping for a key replaces the ones before it.
+        let map = KeyMap::parse("Z = W\nZ = Q");
+
+        assert!(map.pressed(Key::Z, |key| key == Key::Q));
+        assert!(!map.pressed(Key::Z, |key| key == Key::W));
+    }
+}
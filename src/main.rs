@@ -4,27 +4,284 @@
 #![feature(generic_arg_infer)]
 #![feature(let_chains)]
 
+pub mod analyze;
+pub mod audio;
+pub mod cart;
+pub mod check;
 pub mod color;
 pub mod cpu;
+pub mod decode;
 pub mod key;
+pub mod keymap;
+pub mod pad;
+pub mod remote;
 pub mod render;
+pub mod replay;
+pub mod screenshot;
+pub mod sprite;
+pub mod state;
+pub mod storage;
+pub mod terminal;
+
+use audio::WavAudio;
+
+use cart::Cartridge;
+
+use clap::{Arg, ArgMatches, Command};
 
 use cpu::Cpu;
 
-use render::FltkPixels;
+use keymap::{KeyMap, Remapped};
+
+use render::{FltkPixels, Headless, RenderBackend};
+
+use replay::{Recorder, Replayer};
 
-use std::{fs::read, env::args};
+use terminal::Terminal;
+
+use std::{
+    fs::read,
+    panic,
+    path::{Path, PathBuf},
+    process::exit,
+    thread,
+    time::Instant,
+};
 
 pub const WIDTH: usize = 255;
 pub const HEIGHT: usize = 255;
 pub const RES: usize = WIDTH * HEIGHT;
 
+const INTERPRETER_STACK: usize = 8 * 1024 * 1024;
+
 fn main() {
-    let mut cpu: Cpu<FltkPixels> = Cpu::new();
+    let matches = Command::new("atc_console")
+        .about("ATC Fantasy Console")
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("check")
+                .about("Checks a cartridge's code for errors without running it")
+                .arg(Arg::new("file").required(true).help("The .atc file to check")),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Runs a cartridge without a window as fast as possible and reports how long it took")
+                .arg(Arg::new("file").required(true).help("The .atc file to run"))
+                .arg(
+                    Arg::new("frames")
+                        .long("frames")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("600")
+                        .help("Stops after this many flushes"),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Shows a cartridge's metadata")
+                .arg(Arg::new("file").required(true).help("The .atc file to inspect")),
+        )
+        .arg(Arg::new("file").required(true).help("The .atc file to run"))
+        .arg(
+            Arg::new("wav")
+                .long("wav")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Records the audio to a WAV file"),
+        )
+        .arg(
+            Arg::new("terminal")
+                .long("terminal")
+                .help("Draws to the terminal instead of opening a window, e.g. over SSH (Ctrl-C quits)"),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .conflicts_with("terminal")
+                .help("Runs without a window or any input, e.g. with --replay and --screenshot-at-frame"),
+        )
+        .arg(
+            Arg::new("screenshot-at-frame")
+                .long("screenshot-at-frame")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["N", "FILE"])
+                .help("Saves the screen as a .png or .ppm on the Nth flush, then stops"),
+        )
+        .arg(
+            Arg::new("keymap")
+                .long("keymap")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Loads key remappings from a config file [default: atc_keys.cfg, if it exists]"),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("replay")
+                .help("Records all input to a replay file"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Plays back input from a replay file instead of the keyboard and mouse"),
+        )
+        .arg(
+            Arg::new("state")
+                .long("state")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Where F5 saves and F9 loads the console state [default: FILE.state]"),
+        )
+        .arg(
+            Arg::new("load-state")
+                .long("load-state")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Restores a saved console state before starting"),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("check", matches)) => exit(check::run(matches.value_of("file").unwrap())),
+        Some(("bench", matches)) => {
+            let bytecode = read(matches.value_of("file").unwrap()).unwrap();
+            let frames = matches
+                .value_of("frames")
+                .unwrap()
+                .parse()
+                .expect("--frames must be a number");
+
+            bench(bytecode, frames);
+            return;
+        }
+        Some(("info", matches)) => {
+            let path = matches.value_of("file").unwrap();
+            let bytecode = read(path).unwrap();
+
+            match Cartridge::parse(bytecode) {
+                Ok(cart) => print!("{}", cart.info()),
+                Err(e) => {
+                    eprintln!("{path}: failed to load: {e}");
+                    exit(2);
+                }
+            }
 
-    let file_name = args().nth(1).unwrap();
+            return;
+        }
+        _ => {}
+    }
 
-    let bytecode = read(&file_name).unwrap();
+    let file_name = matches.value_of("file").unwrap();
+
+    let bytecode = read(file_name).unwrap();
+
+    let keymap = match matches.value_of("keymap") {
+        Some(path) => KeyMap::load(path),
+        None if Path::new(keymap::DEFAULT_PATH).exists() => KeyMap::load(keymap::DEFAULT_PATH),
+        None => KeyMap::default(),
+    };
+
+    let terminal = matches.is_present("terminal");
+
+    if matches.is_present("headless") {
+        start(Headless::new(usize::MAX), keymap, &matches, bytecode);
+        return;
+    }
+
+    // The window has to stay on the main thread, so the interpreter gets
+    // its own and talks to the window through a `Remote`.
+    let (remote, server) = remote::connect();
+
+    // The CPU holds several frames, which are copied around on the stack
+    // before optimisation, so it gets as much stack as the main thread.
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK)
+        .spawn(move || start(remote, keymap, &matches, bytecode))
+        .unwrap();
+
+    if terminal {
+        server.serve(&mut Terminal::new());
+    } else {
+        server.serve(&mut FltkPixels::new());
+    }
+
+    // The panic message has already been printed by the interpreter's thread.
+    if let Err(e) = interpreter.join() {
+        panic::resume_unwind(e);
+    }
+}
+
+/// Wraps the window in whatever the options ask for, then runs the cartridge.
+fn start<T: RenderBackend>(window: T, keymap: KeyMap, matches: &ArgMatches, bytecode: Vec<u8>) {
+    let window = Remapped::new(window, keymap);
+
+    if let Some(path) = matches.value_of("record") {
+        run(Cpu::new(Recorder::new(window, path)), matches, bytecode);
+    } else if let Some(path) = matches.value_of("replay") {
+        run(Cpu::new(Replayer::new(window, path)), matches, bytecode);
+    } else {
+        run(Cpu::new(window), matches, bytecode);
+    }
+}
+
+fn run<T: RenderBackend>(mut cpu: Cpu<T>, matches: &ArgMatches, bytecode: Vec<u8>) {
+    if let Some(path) = matches.value_of("wav") {
+        cpu.audio = Box::new(WavAudio::new(path));
+    }
+
+    cpu.state_path = match matches.value_of("state") {
+        Some(path) => path.into(),
+        None => format!("{}.state", matches.value_of("file").unwrap()).into(),
+    };
+
+    cpu.resume = matches.value_of("load-state").map(PathBuf::from);
+
+    // Keep persistent storage and screenshots next to the cartridge.
+    let file = Path::new(matches.value_of("file").unwrap());
+    cpu.storage_dir = Some(file.parent().unwrap_or(Path::new(".")).to_path_buf());
+    cpu.screenshot_base = file.with_extension("");
+
+    if let Some(values) = matches.values_of("screenshot-at-frame") {
+        let [frame, path] = values.collect::<Vec<_>>()[..] else {
+            unreachable!()
+        };
+
+        let frame = frame.parse().expect("--screenshot-at-frame must be given a number");
+        assert!(frame > 0, "--screenshot-at-frame counts frames from 1");
+        let path = PathBuf::from(path);
+
+        // Better to find out now than after running the whole cartridge.
+        screenshot::Format::of(&path).unwrap_or_else(|e| panic!("{e}"));
+
+        cpu.screenshot_at = Some((frame, path));
+    }
 
     cpu.run(bytecode);
+
+    if let Some((frame, _)) = cpu.screenshot_at {
+        panic!("The cartridge stopped after {} frames, so there was no frame {frame} to save", cpu.flushes);
+    }
+}
+
+/// Runs a cartridge headless, without audio or persistent storage, for up
+/// to `frames` flushes.
+fn bench(bytecode: Vec<u8>, frames: usize) {
+    let mut cpu = Cpu::new(Headless::new(frames));
+
+    let start = Instant::now();
+    cpu.run(bytecode);
+    let elapsed = start.elapsed();
+
+    let frames = cpu.window.frames;
+
+    println!(
+        "{frames} frames in {elapsed:.2?} ({:.1} frames per second, {} unchanged)",
+        frames as f64 / elapsed.as_secs_f64(),
+        cpu.skipped
+    );
 }
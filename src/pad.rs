@@ -0,0 +1,63 @@
+use crate::key::Key;
+
+/// Number of controllers.
+pub const PLAYERS: usize = 2;
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+// Virtual controller buttons for the ATC Fantasy Console
+pub enum Button {
+    Up = 0x00,
+    Dwn = 0x01,
+    Lft = 0x02,
+    Rght = 0x03,
+    A = 0x04,
+    B = 0x05,
+    X = 0x06,
+    Y = 0x07,
+    Start = 0x08,
+    Select = 0x09,
+}
+
+impl Button {
+    pub fn try_from_hex(hex: u8) -> Option<Button> {
+        Some(match hex {
+            0x00 => Button::Up,
+            0x01 => Button::Dwn,
+            0x02 => Button::Lft,
+            0x03 => Button::Rght,
+            0x04 => Button::A,
+            0x05 => Button::B,
+            0x06 => Button::X,
+            0x07 => Button::Y,
+            0x08 => Button::Start,
+            0x09 => Button::Select,
+            _ => return None,
+        })
+    }
+}
+
+/// The keyboard keys that stand in for each player's controller buttons,
+/// indexed by button code.
+pub struct PadMap([[Key; 10]; PLAYERS]);
+
+impl Default for PadMap {
+    fn default() -> Self {
+        use Key::*;
+
+        PadMap([
+            [Up, Dwn, Lft, Rght, Z, X, C, V, Enter, RShift],
+            [W, S, A, D, F, G, R, T, Tab, LShift],
+        ])
+    }
+}
+
+impl PadMap {
+    pub fn key(&self, player: u8, button: Button) -> Key {
+        let Some(buttons) = self.0.get(player as usize) else {
+            panic!("Player {player} out of bounds. (Zero based indexing!)")
+        };
+
+        buttons[button as usize]
+    }
+}
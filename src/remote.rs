@@ -0,0 +1,232 @@
+use crate::{
+    color::Colour,
+    key::Key,
+    render::{Hotkey, Rect, RenderBackend},
+    RES, WIDTH,
+};
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError},
+    time::Duration,
+};
+
+/// How long the window waits for a frame before processing events anyway,
+/// so that it stays responsive while the program is busy.
+const POLL: Duration = Duration::from_millis(2);
+
+/// Frames the interpreter can get ahead of the window before it has to
+/// wait for them to be shown.
+const QUEUED_FRAMES: usize = 2;
+
+/// Sent from the interpreter to the window.
+enum Message {
+    /// The rows of the frame covered by the dirty rectangle, if anything
+    /// changed.
+    Frame(Option<Rect>, Vec<Colour>),
+    Title(String),
+}
+
+/// State of the input devices, sent from the window to the interpreter
+/// whenever it changes.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Input {
+    held: Vec<Key>,
+    mouse: Option<(usize, usize)>,
+    buttons: [bool; 3],
+    /// Characters typed since the last snapshot.
+    typed: Vec<char>,
+    hotkey: Option<Hotkey>,
+}
+
+impl Input {
+    fn read<T: RenderBackend>(window: &mut T) -> Self {
+        let mut typed = Vec::new();
+
+        while let Some(c) = window.typed() {
+            typed.push(c);
+        }
+
+        Input {
+            held: Key::ALL.into_iter().filter(|key| window.key(*key)).collect(),
+            mouse: window.mouse(),
+            buttons: [0, 1, 2].map(|button| window.mouse_down(button)),
+            typed,
+            hotkey: window.hotkey(),
+        }
+    }
+}
+
+/// Input as last seen by the interpreter.
+#[derive(Default)]
+struct State {
+    input: Input,
+    typed: VecDeque<char>,
+    hotkey: Option<Hotkey>,
+    closed: bool,
+}
+
+/// Stands in for the window on the interpreter's thread. Frames are sent to
+/// the window's thread, which sends back snapshots of the input.
+pub struct Remote {
+    out: SyncSender<Message>,
+    input: Receiver<Input>,
+    state: RefCell<State>,
+}
+
+/// The window's end of a `Remote`.
+pub struct Window {
+    messages: Receiver<Message>,
+    input: Sender<Input>,
+}
+
+/// Connects an interpreter thread to the window's thread.
+pub fn connect() -> (Remote, Window) {
+    let (out, messages) = sync_channel(QUEUED_FRAMES);
+    let (input, input_rx) = channel();
+
+    let remote = Remote {
+        out,
+        input: input_rx,
+        state: RefCell::default(),
+    };
+
+    (remote, Window { messages, input })
+}
+
+impl State {
+    fn apply(&mut self, input: Input) {
+        self.typed.extend(&input.typed);
+        self.hotkey = input.hotkey.or(self.hotkey);
+        self.input = input;
+    }
+}
+
+impl Remote {
+    /// Applies any snapshots the window has sent since the last call.
+    fn poll(&self) {
+        let mut state = self.state.borrow_mut();
+
+        loop {
+            match self.input.try_recv() {
+                Ok(input) => state.apply(input),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    state.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn send(&self, message: Message) {
+        // The window is gone once it stops listening.
+        if self.out.send(message).is_err() {
+            self.state.borrow_mut().closed = true;
+        }
+    }
+}
+
+impl RenderBackend for Remote {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        let rows = match dirty {
+            Some(dirty) => buf[dirty.y0 * WIDTH..dirty.y1 * WIDTH].to_vec(),
+            None => Vec::new(),
+        };
+
+        self.send(Message::Frame(dirty, rows));
+    }
+
+    fn is_open(&self) -> bool {
+        self.poll();
+
+        !self.state.borrow().closed
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.send(Message::Title(title.to_string()))
+    }
+
+    fn key(&self, key: Key) -> bool {
+        self.state.borrow().input.held.contains(&key)
+    }
+
+    fn mouse(&self) -> Option<(usize, usize)> {
+        self.state.borrow().input.mouse
+    }
+
+    fn mouse_down(&self, button: u8) -> bool {
+        self.state.borrow().input.buttons.get(button as usize).copied().unwrap_or(false)
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        self.poll();
+
+        self.state.borrow_mut().typed.pop_front()
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.state.borrow_mut().hotkey.take()
+    }
+
+    fn wait_input(&self, timeout: Duration) {
+        // The window only sends a snapshot once something changes.
+        match self.input.recv_timeout(timeout) {
+            Ok(input) => self.state.borrow_mut().apply(input),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => self.state.borrow_mut().closed = true,
+        }
+    }
+
+    fn wait_closed(&self, timeout: Duration) -> bool {
+        // The window's thread hangs up once it is closed. Input that arrives
+        // in the meantime isn't needed any more.
+        matches!(self.input.recv_timeout(timeout), Err(RecvTimeoutError::Disconnected))
+    }
+}
+
+impl Window {
+    /// Shows frames from the interpreter and sends it the input until either
+    /// the window is closed or the interpreter is done with it.
+    pub fn serve<T: RenderBackend>(self, window: &mut T) {
+        let mut buf: Box<[Colour; RES]> = vec![Colour::Green; RES].into_boxed_slice().try_into().unwrap();
+        let mut last = Input::default();
+
+        loop {
+            match self.messages.recv_timeout(POLL) {
+                Ok(Message::Frame(dirty, rows)) => {
+                    if let Some(dirty) = dirty {
+                        buf[dirty.y0 * WIDTH..dirty.y1 * WIDTH].copy_from_slice(&rows);
+                    }
+
+                    window.update(&buf, dirty);
+                }
+                Ok(Message::Title(title)) => window.set_title(&title),
+                // Nothing new to show, but events still need processing.
+                Err(RecvTimeoutError::Timeout) => window.update(&buf, None),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            window.fltk_up();
+
+            if !window.is_open() {
+                break;
+            }
+
+            let input = Input::read(window);
+
+            if input != last {
+                if self.input.send(input.clone()).is_err() {
+                    break;
+                }
+
+                last = Input {
+                    typed: Vec::new(),
+                    hotkey: None,
+                    ..input
+                };
+            }
+        }
+    }
+}
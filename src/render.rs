@@ -1,27 +1,150 @@
 use crate::{color::Colour, key::Key, HEIGHT, RES, WIDTH};
 
 use fltk::{
-    enums::{Key as FKey},
     app::{self, App},
+    enums::{Event, Key as FKey},
     prelude::*,
     window::{Window as FWin},
 };
-use minifb::{Window as MWin, WindowOptions};
+use minifb::{InputCallback, Key as MKey, KeyRepeat, MouseButton, MouseMode, Window as MWin, WindowOptions};
 use pixels::{Pixels, SurfaceTexture};
 
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    ops::Range,
+    rc::Rc,
+    time::Duration,
+};
+
+/// Characters typed into the window that have not been read yet.
+type CharQueue = Rc<RefCell<VecDeque<char>>>;
+
+/// Console functions that are bound to keys the cartridge can't see.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Hotkey {
+    /// F5
+    SaveState,
+    /// F9
+    LoadState,
+    /// F12
+    Screenshot,
+}
+
+/// Part of the screen, as half-open ranges of columns (`x0..x1`)
+/// and rows (`y0..y1`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Rect {
+    pub const FULL: Rect = Rect {
+        x0: 0,
+        y0: 0,
+        x1: WIDTH,
+        y1: HEIGHT,
+    };
+
+    /// A `w` by `h` rectangle, cut off at the edges of the screen.
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> Self {
+        Rect {
+            x0: x.min(WIDTH),
+            y0: y.min(HEIGHT),
+            x1: (x + w).min(WIDTH),
+            y1: (y + h).min(HEIGHT),
+        }
+    }
+
+    /// The single pixel at index `i` of the screen.
+    pub fn pixel(i: usize) -> Self {
+        Rect::new(i % WIDTH, i / WIDTH, 1, 1)
+    }
+
+    /// A `w` by `h` rectangle that wraps around the edges of the screen.
+    /// Parts that wrap widen it to the whole width or height.
+    pub fn wrapping(x: usize, y: usize, w: usize, h: usize) -> Self {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+
+        let (x0, x1) = if x + w > WIDTH { (0, WIDTH) } else { (x, x + w) };
+        let (y0, y1) = if y + h > HEIGHT { (0, HEIGHT) } else { (y, y + h) };
+
+        Rect { x0, y0, x1, y1 }
+    }
+
+    /// The smallest rectangle covering both.
+    pub fn union(self, other: Rect) -> Rect {
+        Rect {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    pub fn rows(&self) -> Range<usize> {
+        self.y0..self.y1
+    }
+
+    pub fn area(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
 pub trait RenderBackend {
-    /// Updates the screen. Should panic on error, since
+    /// Updates the screen. `dirty` covers every pixel that changed since
+    /// the last update, or is `None` if nothing did, in which case only
+    /// events need to be processed. Should panic on error, since
     /// this is a failure on the interpreter side that
     /// cannot be handled by the user.
-    fn update(&mut self, buf: [Colour; RES]);
-
-    fn new() -> Self;
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>);
 
     fn is_open(&self) -> bool;
 
+    fn set_title(&mut self, _title: &str) {}
+
+    /// Whether the key is currently held down.
     fn key(&self, key: Key) -> bool;
 
+    /// Position of the mouse pointer in console coordinates,
+    /// or `None` if it is outside the window.
+    fn mouse(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Whether a mouse button is held. `0` is the left button,
+    /// `1` the middle button and `2` the right button.
+    fn mouse_down(&self, _button: u8) -> bool {
+        false
+    }
+
+    /// Takes the next typed character off the input queue.
+    fn typed(&mut self) -> Option<char> {
+        None
+    }
+
+    /// Takes the hotkey that was pressed since the last call, if any.
+    fn hotkey(&self) -> Option<Hotkey> {
+        None
+    }
+
     fn fltk_up(&self) {}
+
+    /// Waits up to `timeout` for new input, if the backend can, rather than
+    /// spinning while nothing has been typed.
+    fn wait_input(&self, _timeout: Duration) {
+        self.fltk_up()
+    }
+
+    /// Waits up to `timeout` for the window to be closed, if the backend
+    /// can, returning whether it has been. Used to keep the last frame on
+    /// screen once a cartridge with the keep open flag is over.
+    fn wait_closed(&self, _timeout: Duration) -> bool {
+        !self.is_open()
+    }
 }
 
 impl From<Colour> for u32 {
@@ -30,57 +153,123 @@ impl From<Colour> for u32 {
     }
 }
 
-impl RenderBackend for MWin {
-    fn update(&mut self, buf: [Colour; HEIGHT * WIDTH]) {
-        self.update_with_buffer(&buf.map(u32::from), WIDTH, HEIGHT)
-            .unwrap()
+/// The window, its typed characters and the buffer frames are converted
+/// into before being shown.
+pub struct Minifb(MWin, CharQueue, Vec<u32>);
+
+struct MinifbInput(CharQueue);
+
+impl InputCallback for MinifbInput {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char) {
+            self.0.borrow_mut().push_back(c);
+        }
     }
+}
 
-    fn new() -> Self {
-        Self::new(
+impl Minifb {
+    pub fn new() -> Self {
+        let mut win = MWin::new(
             "ATC Fantasy Console",
             WIDTH,
             HEIGHT,
             WindowOptions::default(),
         )
-        .unwrap()
+        .unwrap();
+
+        let queue = CharQueue::default();
+        win.set_input_callback(Box::new(MinifbInput(queue.clone())));
+
+        Self(win, queue, vec![0; RES])
+    }
+}
+
+impl Default for Minifb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for Minifb {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        let Some(dirty) = dirty else {
+            self.0.update();
+            return;
+        };
+
+        let rows = dirty.y0 * WIDTH..dirty.y1 * WIDTH;
+
+        for (pix, clr) in self.2[rows.clone()].iter_mut().zip(&buf[rows]) {
+            *pix = u32::from(*clr);
+        }
+
+        self.0.update_with_buffer(&self.2, WIDTH, HEIGHT).unwrap()
     }
 
     fn is_open(&self) -> bool {
-        self.is_open()
+        self.0.is_open()
     }
 
-    fn key(&self, key: Key) -> bool {
-        self.is_key_pressed(key.to_fb_key(), minifb::KeyRepeat::No)
+    fn set_title(&mut self, title: &str) {
+        self.0.set_title(title)
     }
 
-    fn fltk_up(&self) {}
-}
+    fn key(&self, key: Key) -> bool {
+        self.0.is_key_down(key.to_fb_key())
+    }
 
-pub struct FltkPixels(FWin, App, Pixels);
+    fn mouse(&self) -> Option<(usize, usize)> {
+        let (x, y) = self.0.get_mouse_pos(MouseMode::Discard)?;
+        let (w, h) = self.0.get_size();
 
-impl RenderBackend for FltkPixels {
-    fn update(&mut self, buf: [Colour; RES]) {
-        let pixels = self.2.get_frame().chunks_exact_mut(4);
+        Some((x as usize * WIDTH / w, y as usize * HEIGHT / h))
+    }
 
-        for (pix, new) in pixels.zip(buf) {
-            pix.copy_from_slice(&new.into_rgba());
+    fn mouse_down(&self, button: u8) -> bool {
+        match button {
+            0 => self.0.get_mouse_down(MouseButton::Left),
+            1 => self.0.get_mouse_down(MouseButton::Middle),
+            2 => self.0.get_mouse_down(MouseButton::Right),
+            _ => false,
         }
+    }
 
-        if self
-            .2
-            .render()
-            .map_err(|e| eprintln!("pixels.render() failed: {:?}", e))
-            .is_err()
-        {
-            self.1.quit();
+    fn typed(&mut self) -> Option<char> {
+        // minifb only processes events when the window is updated.
+        if self.1.borrow().is_empty() {
+            self.0.update();
         }
 
-        app::flush();
-        app::awake();
+        self.1.borrow_mut().pop_front()
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        if self.0.is_key_pressed(MKey::F5, KeyRepeat::No) {
+            Some(Hotkey::SaveState)
+        } else if self.0.is_key_pressed(MKey::F9, KeyRepeat::No) {
+            Some(Hotkey::LoadState)
+        } else if self.0.is_key_pressed(MKey::F12, KeyRepeat::No) {
+            Some(Hotkey::Screenshot)
+        } else {
+            None
+        }
     }
 
-    fn new() -> Self {
+    fn fltk_up(&self) {}
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct FltkInput {
+    /// Mouse position relative to the window, in screen pixels.
+    pos: Option<(i32, i32)>,
+    buttons: [bool; 3],
+    hotkey: Option<Hotkey>,
+}
+
+pub struct FltkPixels(FWin, App, Pixels, CharQueue, Rc<Cell<FltkInput>>);
+
+impl FltkPixels {
+    pub fn new() -> Self {
         let app = App::default();
         let mut win = FWin::default()
             .with_label("ATC Fantasy Console")
@@ -88,6 +277,53 @@ impl RenderBackend for FltkPixels {
 
         win.end();
 
+        // By default, FLTK also closes the window when Escape is pressed,
+        // but cartridges can use Escape as a key.
+        win.set_callback(|win| {
+            if app::event() == Event::Close {
+                win.hide();
+            }
+        });
+
+        let queue = CharQueue::default();
+        let input = Rc::new(Cell::new(FltkInput::default()));
+        win.handle({
+            let queue = queue.clone();
+            let input = input.clone();
+            move |_, event| {
+                let mut state = input.get();
+
+                match event {
+                    Event::KeyDown => match app::event_key() {
+                        FKey::F5 => state.hotkey = Some(Hotkey::SaveState),
+                        FKey::F9 => state.hotkey = Some(Hotkey::LoadState),
+                        FKey::F12 => state.hotkey = Some(Hotkey::Screenshot),
+                        _ => queue.borrow_mut().extend(app::event_text().chars()),
+                    },
+                    Event::Enter | Event::Move | Event::Drag => state.pos = Some(app::event_coords()),
+                    Event::Leave => state.pos = None,
+                    Event::Push | Event::Released => {
+                        let button = match app::event_mouse_button() {
+                            app::MouseButton::Left => 0,
+                            app::MouseButton::Middle => 1,
+                            app::MouseButton::Right => 2,
+                            // Extra buttons, e.g. back and forward.
+                            _ => return false,
+                        };
+
+                        state.buttons[button] = event == Event::Push;
+                    }
+                    _ => {}
+                }
+
+                input.set(state);
+
+                // The window has to accept these events to keep
+                // being sent pointer movement and releases.
+                matches!(event, Event::Enter | Event::Push)
+            }
+        });
+
         win.show();
 
         let pixel_width = win.pixel_w() as u32;
@@ -95,36 +331,164 @@ impl RenderBackend for FltkPixels {
         let surface_texture = SurfaceTexture::new(pixel_width, pixel_height, &win);
         let pixels = Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture).unwrap();
 
-        Self(win, app, pixels)
+        Self(win, app, pixels, queue, input)
+    }
+}
+
+impl Default for FltkPixels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for FltkPixels {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        // The whole frame is uploaded on render, so there is
+        // nothing to gain from rendering an unchanged one.
+        if let Some(dirty) = dirty {
+            let frame = &mut self.2.get_frame()[dirty.y0 * WIDTH * 4..dirty.y1 * WIDTH * 4];
+            let rows = dirty.y0 * WIDTH..dirty.y1 * WIDTH;
+
+            for (pix, new) in frame.chunks_exact_mut(4).zip(&buf[rows]) {
+                pix.copy_from_slice(&new.into_rgba());
+            }
+
+            if self
+                .2
+                .render()
+                .map_err(|e| eprintln!("pixels.render() failed: {:?}", e))
+                .is_err()
+            {
+                self.1.quit();
+            }
+        }
+
+        app::flush();
+        app::awake();
     }
 
     fn is_open(&self) -> bool {
         self.1.wait()
     }
 
+    fn set_title(&mut self, title: &str) {
+        self.0.set_label(title)
+    }
+
     fn key(&self, key: Key) -> bool {
-        // Could defintely be handled better.
-
-        if app::event_text().is_empty() {
-            return match app::event_key() {
-                FKey::Up => Key::Up == key,
-                FKey::Down => Key::Dwn == key,
-                FKey::Left => Key::Lft == key,
-                FKey::Right => Key::Rght == key,
-                FKey::ControlL => Key::LCtrl == key,
-                FKey::ControlR => Key::RCtrl == key,
-                _ => false
-            }
-        }
+        app::event_key_down(key.to_fltk_key())
+    }
 
-        if let Some(mat) = Key::from_str(&app::event_text()) && mat == key {
-            true
-        } else {
-            false
-        }
+    fn mouse(&self) -> Option<(usize, usize)> {
+        let (x, y) = self.4.get().pos?;
+
+        // The window is scaled up from the console's resolution.
+        let x = x.clamp(0, self.0.w() - 1) as usize * WIDTH / self.0.w() as usize;
+        let y = y.clamp(0, self.0.h() - 1) as usize * HEIGHT / self.0.h() as usize;
+
+        Some((x, y))
+    }
+
+    fn mouse_down(&self, button: u8) -> bool {
+        self.4.get().buttons.get(button as usize).copied().unwrap_or(false)
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        self.3.borrow_mut().pop_front()
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        let mut state = self.4.get();
+        let hotkey = state.hotkey.take();
+        self.4.set(state);
+
+        hotkey
     }
 
     fn fltk_up(&self) {
         app::awake();
     }
 }
+
+/// Keeps frames in memory instead of drawing them and reads no input, for
+/// running cartridges without a display. Closes itself once `limit` frames
+/// have been flushed.
+pub struct Headless {
+    pub frames: usize,
+    pub limit: usize,
+    /// The last frame that was flushed.
+    pub frame: Vec<Colour>,
+}
+
+impl Headless {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            frames: 0,
+            limit,
+            frame: vec![Colour::Green; RES],
+        }
+    }
+}
+
+impl RenderBackend for Headless {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        if let Some(dirty) = dirty {
+            let rows = dirty.y0 * WIDTH..dirty.y1 * WIDTH;
+            self.frame[rows.clone()].copy_from_slice(&buf[rows]);
+        }
+
+        self.frames += 1;
+    }
+
+    fn is_open(&self) -> bool {
+        self.frames < self.limit
+    }
+
+    fn key(&self, _key: Key) -> bool {
+        false
+    }
+
+    // There's nobody to close it.
+    fn wait_closed(&self, _timeout: Duration) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x0: usize, y0: usize, x1: usize, y1: usize) -> Rect {
+        Rect { x0, y0, x1, y1 }
+    }
+
+    #[test]
+    fn new_is_cut_off_at_the_edges() {
+        assert_eq!(Rect::new(10, 20, 8, 8), rect(10, 20, 18, 28));
+        assert_eq!(Rect::new(250, 252, 8, 8), rect(250, 252, WIDTH, HEIGHT));
+        assert_eq!(Rect::new(300, 0, 8, 8).area(), 0);
+    }
+
+    #[test]
+    fn pixel_past_the_right_edge() {
+        assert_eq!(Rect::pixel(3 + 2 * WIDTH), rect(3, 2, 4, 3));
+        assert_eq!(Rect::pixel(WIDTH), rect(0, 1, 1, 2));
+    }
+
+    #[test]
+    fn wrapping_widens_to_the_whole_screen() {
+        assert_eq!(Rect::wrapping(10, 20, 8, 8), rect(10, 20, 18, 28));
+        assert_eq!(Rect::wrapping(250, 20, 8, 8), rect(0, 20, WIDTH, 28));
+        assert_eq!(Rect::wrapping(10, 250, 8, 8), rect(10, 0, 18, HEIGHT));
+        assert_eq!(Rect::wrapping(WIDTH + 1, HEIGHT + 2, 8, 8), rect(1, 2, 9, 10));
+    }
+
+    #[test]
+    fn union_covers_both() {
+        let union = rect(10, 20, 18, 28).union(rect(0, 30, 5, 31));
+
+        assert_eq!(union, rect(0, 20, 18, 31));
+        assert_eq!(union.rows(), 20..31);
+        assert_eq!(union.area(), 18 * 11);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::{color::Colour, key::Key, render::{Hotkey, Rect, RenderBackend}, RES};
+
+use std::{
+    cell::{Cell, RefCell},
+    fs::{read, File},
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+const MAGIC: &[u8; 4] = b"ATCR";
+const VERSION: u8 = 1;
+
+// Every input query is stored as a tag byte followed by its result.
+const FRAME: u8 = b'f';
+const KEY: u8 = b'k';
+const MOUSE: u8 = b'm';
+const BUTTON: u8 = b'b';
+const TYPED: u8 = b't';
+/// A run of typed character queries that found nothing, with its length,
+/// since programs can poll for typing thousands of times a frame.
+const EMPTY: u8 = b'e';
+
+/// Loading a state changes how the program runs in a way a replay can't
+/// reproduce, so it is turned off while recording or replaying. Saving
+/// states and taking screenshots don't affect the program.
+fn allowed(hotkey: Option<Hotkey>) -> Option<Hotkey> {
+    match hotkey {
+        Some(Hotkey::LoadState) => {
+            eprintln!("Loading states is turned off while recording or replaying");
+            None
+        }
+        hotkey => hotkey,
+    }
+}
+
+/// Wraps a backend, writing the result of every input query to a replay file.
+pub struct Recorder<T: RenderBackend> {
+    inner: T,
+    file: RefCell<BufWriter<File>>,
+    /// Typed character queries that found nothing since the last record.
+    empty: Cell<u32>,
+}
+
+impl<T: RenderBackend> Recorder<T> {
+    pub fn new(inner: T, path: &str) -> Self {
+        let mut file = BufWriter::new(
+            File::create(path).unwrap_or_else(|e| panic!("Failed to create replay file {path}: {e}")),
+        );
+
+        file.write_all(MAGIC).unwrap();
+        file.write_all(&[VERSION]).unwrap();
+
+        Recorder {
+            inner,
+            file: RefCell::new(file),
+            empty: Cell::new(0),
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        self.file
+            .borrow_mut()
+            .write_all(bytes)
+            .expect("Failed to write to replay file");
+    }
+
+    /// Writes out the current run of empty typed character queries, if any.
+    fn end_run(&self) {
+        let run = self.empty.replace(0);
+
+        if run > 0 {
+            self.write(&[EMPTY]);
+            self.write(&run.to_le_bytes());
+        }
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        self.end_run();
+        self.write(bytes);
+    }
+}
+
+impl<T: RenderBackend> Drop for Recorder<T> {
+    fn drop(&mut self) {
+        self.end_run();
+    }
+}
+
+impl<T: RenderBackend> RenderBackend for Recorder<T> {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        self.inner.update(buf, dirty);
+        self.record(&[FRAME]);
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.inner.set_title(title)
+    }
+
+    fn key(&self, key: Key) -> bool {
+        let down = self.inner.key(key);
+        self.record(&[KEY, key as u8, down as u8]);
+        down
+    }
+
+    fn mouse(&self) -> Option<(usize, usize)> {
+        let pos = self.inner.mouse();
+
+        match pos {
+            Some((x, y)) => self.record(&[MOUSE, 1, x as u8, y as u8]),
+            None => self.record(&[MOUSE, 0, 0, 0]),
+        }
+
+        pos
+    }
+
+    fn mouse_down(&self, button: u8) -> bool {
+        let down = self.inner.mouse_down(button);
+        self.record(&[BUTTON, button, down as u8]);
+        down
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        let c = self.inner.typed();
+
+        match c {
+            Some(c) => {
+                self.record(&[TYPED]);
+                self.write(&u32::from(c).to_le_bytes());
+            }
+            None if self.empty.get() == u32::MAX => {
+                self.end_run();
+                self.empty.set(1);
+            }
+            None => self.empty.set(self.empty.get() + 1),
+        }
+
+        c
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        allowed(self.inner.hotkey())
+    }
+
+    fn fltk_up(&self) {
+        self.inner.fltk_up()
+    }
+
+    fn wait_input(&self, timeout: Duration) {
+        self.inner.wait_input(timeout)
+    }
+
+    fn wait_closed(&self, timeout: Duration) -> bool {
+        self.inner.wait_closed(timeout)
+    }
+}
+
+/// Wraps a backend, drawing to it but answering every input query
+/// from a replay file. The window closes once the replay runs out.
+pub struct Replayer<T: RenderBackend> {
+    inner: T,
+    log: Vec<u8>,
+    pos: Cell<usize>,
+    /// What's left of the current run of empty typed character queries.
+    empty: Cell<u32>,
+}
+
+impl<T: RenderBackend> Replayer<T> {
+    pub fn new(inner: T, path: &str) -> Self {
+        let log = read(path).unwrap_or_else(|e| panic!("Failed to read replay file {path}: {e}"));
+
+        let Some(version) = log.strip_prefix(MAGIC).and_then(|rest| rest.first()) else {
+            panic!("{path} is not a replay file");
+        };
+
+        if *version != VERSION {
+            panic!("Unsupported replay version {version} (expected {VERSION})");
+        }
+
+        Replayer {
+            inner,
+            log,
+            pos: Cell::new(MAGIC.len() + 1),
+            empty: Cell::new(0),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.pos.get() >= self.log.len()
+    }
+
+    /// Reads the next record, which has to have been made by the same kind
+    /// of query. Returns `None` once the replay has run out.
+    fn next<const N: usize>(&self, tag: u8) -> Option<[u8; N]> {
+        if self.finished() {
+            return None;
+        }
+
+        let pos = self.pos.get();
+
+        let Some(record) = self.log.get(pos..pos + 1 + N) else {
+            panic!("Replay file is truncated at byte {pos}")
+        };
+
+        if record[0] != tag {
+            panic!(
+                "Replay diverged at byte {pos}: expected a {:?} record, found {:?}",
+                tag as char, record[0] as char
+            );
+        }
+
+        self.pos.set(pos + 1 + N);
+
+        Some(record[1..].try_into().unwrap())
+    }
+}
+
+impl<T: RenderBackend> RenderBackend for Replayer<T> {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        self.inner.update(buf, dirty);
+        self.next::<0>(FRAME);
+    }
+
+    fn is_open(&self) -> bool {
+        !self.finished() && self.inner.is_open()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.inner.set_title(title)
+    }
+
+    fn key(&self, key: Key) -> bool {
+        let Some([code, down]) = self.next(KEY) else {
+            return false;
+        };
+
+        if code != key as u8 {
+            panic!("Replay diverged: expected a query for key {key:?}, found {code:0>2x}");
+        }
+
+        down == 1
+    }
+
+    fn mouse(&self) -> Option<(usize, usize)> {
+        match self.next(MOUSE)? {
+            [1, x, y] => Some((x as usize, y as usize)),
+            _ => None,
+        }
+    }
+
+    fn mouse_down(&self, button: u8) -> bool {
+        let Some([recorded, down]) = self.next(BUTTON) else {
+            return false;
+        };
+
+        if recorded != button {
+            panic!("Replay diverged: expected a query for mouse button {button}, found {recorded}");
+        }
+
+        down == 1
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        if self.empty.get() == 0 && self.log.get(self.pos.get()) == Some(&EMPTY) {
+            self.empty.set(u32::from_le_bytes(self.next(EMPTY)?));
+        }
+
+        if self.empty.get() > 0 {
+            self.empty.set(self.empty.get() - 1);
+            return None;
+        }
+
+        let code = u32::from_le_bytes(self.next(TYPED)?);
+
+        Some(char::from_u32(code).unwrap_or_else(|| panic!("Replay file has an invalid character {code:x}")))
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        allowed(self.inner.hotkey())
+    }
+
+    fn fltk_up(&self) {
+        self.inner.fltk_up()
+    }
+
+    fn wait_closed(&self, timeout: Duration) -> bool {
+        self.inner.wait_closed(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Headless;
+
+    use std::{collections::VecDeque, env, fs, process};
+
+    /// Answers input queries from a script that changes every frame.
+    struct Scripted {
+        frame: usize,
+        typed: VecDeque<Option<char>>,
+    }
+
+    impl RenderBackend for Scripted {
+        fn update(&mut self, _buf: &[Colour; RES], _dirty: Option<Rect>) {
+            self.frame += 1;
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+
+        fn key(&self, key: Key) -> bool {
+            key == Key::Spc && self.frame % 2 == 1
+        }
+
+        fn mouse(&self) -> Option<(usize, usize)> {
+            (self.frame > 0).then(|| (self.frame, self.frame * 2))
+        }
+
+        fn mouse_down(&self, button: u8) -> bool {
+            button as usize == self.frame
+        }
+
+        fn typed(&mut self) -> Option<char> {
+            self.typed.pop_front().flatten()
+        }
+    }
+
+    /// Answers to a frame's key, mouse, mouse button and typing queries.
+    type Answers = (bool, Option<(usize, usize)>, bool, [Option<char>; 3]);
+
+    /// Makes the same queries a few frames in a row, returning the answers.
+    fn play(backend: &mut impl RenderBackend) -> Vec<Answers> {
+        let buf = [Colour::Green; RES];
+
+        (0..3)
+            .map(|_| {
+                let answers = (
+                    backend.key(Key::Spc),
+                    backend.mouse(),
+                    backend.mouse_down(1),
+                    [backend.typed(), backend.typed(), backend.typed()],
+                );
+
+                backend.update(&buf, None);
+
+                answers
+            })
+            .collect()
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let path = env::temp_dir().join(format!("atc_console-{}-round-trip.atcr", process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut scripted = Scripted {
+            frame: 0,
+            typed: VecDeque::from([None, None, Some('h'), None, Some('i')]),
+        };
+        let expected = play(&mut scripted);
+
+        scripted.frame = 0;
+        scripted.typed = VecDeque::from([None, None, Some('h'), None, Some('i')]);
+
+        let mut recorder = Recorder::new(scripted, path);
+        let recorded = play(&mut recorder);
+        drop(recorder);
+
+        let mut replayer = Replayer::new(Headless::new(usize::MAX), path);
+        let replayed = play(&mut replayer);
+        let finished = !replayer.is_open();
+
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(recorded, expected);
+        assert_eq!(replayed, expected);
+        assert!(finished);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{cart::crc32, color::Colour, HEIGHT, RES, WIDTH};
+
+use std::{fs::write, path::Path};
+
+/// Image formats screenshots can be saved as, picked by file extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Ppm,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Result<Format, String> {
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Ok(Format::Png),
+            "ppm" => Ok(Format::Ppm),
+            _ => Err(format!("Can't save a screenshot as {}, use .png or .ppm", path.display())),
+        }
+    }
+}
+
+/// Writes the screen to an image file, in the format its extension asks for.
+pub fn save(path: &Path, buf: &[Colour; RES]) -> Result<(), String> {
+    let data = match Format::of(path)? {
+        Format::Png => png(buf),
+        Format::Ppm => ppm(buf),
+    };
+
+    write(path, data).map_err(|e| e.to_string())
+}
+
+/// Every pixel as R, G and B bytes, row by row.
+fn rgb(row: &[Colour]) -> impl Iterator<Item = u8> + '_ {
+    row.iter().flat_map(|clr| {
+        let [r, g, b, _] = clr.into_rgba();
+        [r, g, b]
+    })
+}
+
+/// Binary PPM (`P6`), which has no compression at all.
+fn ppm(buf: &[Colour; RES]) -> Vec<u8> {
+    let mut out = format!("P6\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+    out.extend(rgb(buf));
+
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Appends a PNG chunk, with its length and checksum.
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// 8-bit RGB PNG. The image data is stored without compression, which
+/// keeps the encoder tiny; a screenshot is under 200 KB either way.
+fn png(buf: &[Colour; RES]) -> Vec<u8> {
+    // Each row starts with its filter type, which is always none.
+    let mut raw = Vec::with_capacity(HEIGHT * (1 + WIDTH * 3));
+
+    for row in buf.chunks_exact(WIDTH) {
+        raw.push(0);
+        raw.extend(rgb(row));
+    }
+
+    // A zlib stream made of stored deflate blocks, which hold at most
+    // 65535 bytes each.
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(block);
+    }
+
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // 8 bits per channel, RGB, then the default compression, filter
+    // and interlace methods.
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Box<[Colour; RES]> {
+        let mut buf: Box<[Colour; RES]> = vec![Colour::Black; RES].into_boxed_slice().try_into().unwrap();
+        buf[1] = Colour::White;
+        buf[WIDTH] = Colour::White;
+
+        buf
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn format_by_extension() {
+        assert_eq!(Format::of(Path::new("a.PNG")), Ok(Format::Png));
+        assert_eq!(Format::of(Path::new("a.ppm")), Ok(Format::Ppm));
+        assert!(Format::of(Path::new("a.bmp")).is_err());
+        assert!(Format::of(Path::new("a")).is_err());
+    }
+
+    #[test]
+    fn ppm_layout() {
+        let ppm = ppm(&frame());
+        let [r, g, b, _] = Colour::White.into_rgba();
+        let header = b"P6\n255 255\n255\n";
+
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + RES * 3);
+        assert_eq!(ppm[header.len()..header.len() + 6], [0, 0, 0, r, g, b]);
+    }
+
+    #[test]
+    fn png_layout() {
+        let png = png(&frame());
+        let [r, g, b, _] = Colour::White.into_rgba();
+
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        // Walks the chunks, checking each checksum.
+        let mut pos = 8;
+        let mut chunks = Vec::new();
+
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+
+            assert_eq!(crc32(body), crc);
+            chunks.push((&body[..4], &body[4..]));
+
+            pos += 12 + len;
+        }
+
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 255, 0, 0, 0, 255, 8, 2, 0, 0, 0]);
+
+        // Reads the stored blocks back out of the zlib stream.
+        let zlib = chunks[1].1;
+        let mut raw = Vec::new();
+        let mut pos = 2;
+
+        loop {
+            let last = zlib[pos] == 1;
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]) as usize;
+            raw.extend(&zlib[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+
+            if last {
+                break;
+            }
+        }
+
+        assert_eq!(zlib[pos..], adler32(&raw).to_be_bytes());
+        assert_eq!(raw.len(), HEIGHT * (1 + WIDTH * 3));
+        // Filter byte, then the first two pixels of each of the first two rows.
+        assert_eq!(raw[..7], [0, 0, 0, 0, r, g, b]);
+        assert_eq!(raw[1 + WIDTH * 3..1 + WIDTH * 3 + 7], [0, r, g, b, 0, 0, 0]);
+    }
+}
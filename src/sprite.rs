@@ -0,0 +1,179 @@
+use crate::{color::Colour, cpu::ByteCode};
+
+/// An 8x8 block of colours, stored row by row.
+pub type Tile = [[Colour; 8]; 8];
+
+/// Read-only tiles loaded from the asset section of the cartridge header.
+#[derive(Default)]
+pub struct SpriteSheet(Vec<Tile>);
+
+impl SpriteSheet {
+    /// Reads a tile count followed by 64 colour codes per tile,
+    /// appending them to the sheet.
+    pub fn read(&mut self, bytecode: &mut ByteCode) -> Result<(), String> {
+        let count = bytecode.next().ok_or("Expected tile count in asset section")?;
+
+        for _ in 0..count {
+            let mut tile = [[Colour::Transparent; 8]; 8];
+
+            for row in tile.iter_mut() {
+                for pix in row.iter_mut() {
+                    let num = bytecode.next().ok_or("Unexpected EOF in asset section")?;
+
+                    *pix = Colour::try_from_hex(num).ok_or_else(|| format!("Unknown colour hexcode: {num:0>2x}!"))?;
+                }
+            }
+
+            self.0.push(tile);
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, id: u8) -> &Tile {
+        self.0
+            .get(id as usize)
+            .unwrap_or_else(|| panic!("Tile {id:0>2x} is not in the sprite sheet ({} tiles)", self.0.len()))
+    }
+}
+
+/// Colour codes of an 8x8 sprite, as read from eight byte arrays in memory.
+pub type Mask = [[u8; 8]; 8];
+
+/// Whether the 8x8 boxes of two sprites at `a` and `b` overlap.
+pub fn boxes_overlap(a: (i64, i64), b: (i64, i64)) -> bool {
+    a.0.abs_diff(b.0) < 8 && a.1.abs_diff(b.1) < 8
+}
+
+/// Whether two sprites at `a` and `b` have an opaque pixel in the same
+/// place. Transparent (`0x99`) pixels never collide.
+pub fn masks_overlap(a: (i64, i64), a_mask: &Mask, b: (i64, i64), b_mask: &Mask) -> bool {
+    for (y, row) in a_mask.iter().enumerate() {
+        for (x, pix) in row.iter().enumerate() {
+            if *pix == 0x99 {
+                continue;
+            }
+
+            // Position of this pixel relative to sprite B.
+            let bx_offset = a.0 + x as i64 - b.0;
+            let by_offset = a.1 + y as i64 - b.1;
+
+            if !(0..8).contains(&bx_offset) || !(0..8).contains(&by_offset) {
+                continue;
+            }
+
+            if b_mask[by_offset as usize][bx_offset as usize] != 0x99 {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Width and height of the background tile map, in tiles.
+pub const MAP_SIZE: usize = 32;
+
+/// Tile-based background layer. It is composited beneath the framebuffer
+/// on flush, showing through wherever the framebuffer is transparent.
+pub struct TileMap {
+    pub tiles: [[Option<u8>; MAP_SIZE]; MAP_SIZE],
+    pub scroll_x: usize,
+    pub scroll_y: usize,
+}
+
+impl Default for TileMap {
+    fn default() -> Self {
+        TileMap {
+            tiles: [[None; MAP_SIZE]; MAP_SIZE],
+            scroll_x: 0,
+            scroll_y: 0,
+        }
+    }
+}
+
+impl TileMap {
+    /// Colour of the background at the given screen position, after scrolling.
+    /// The map wraps around in both directions.
+    pub fn pixel(&self, sheet: &SpriteSheet, x: usize, y: usize) -> Colour {
+        let x = (x + self.scroll_x) % (MAP_SIZE * 8);
+        let y = (y + self.scroll_y) % (MAP_SIZE * 8);
+
+        match self.tiles[y / 8][x / 8] {
+            Some(id) => sheet.get(id)[y % 8][x % 8],
+            None => Colour::Transparent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sprite that is opaque only where `opaque` says so.
+    fn mask(opaque: impl Fn(usize, usize) -> bool) -> Mask {
+        let mut mask = [[0x99; 8]; 8];
+
+        for (y, row) in mask.iter_mut().enumerate() {
+            for (x, pix) in row.iter_mut().enumerate() {
+                if opaque(x, y) {
+                    *pix = 0x1a;
+                }
+            }
+        }
+
+        mask
+    }
+
+    #[test]
+    fn boxes_touching_edges() {
+        assert!(boxes_overlap((0, 0), (7, 7)));
+        assert!(!boxes_overlap((0, 0), (8, 0)));
+        assert!(!boxes_overlap((0, 0), (0, 8)));
+        assert!(boxes_overlap((-3, -3), (3, 4)));
+        assert!(!boxes_overlap((-8, 0), (0, 0)));
+    }
+
+    #[test]
+    fn masks_touching_edges() {
+        let full = mask(|_, _| true);
+
+        assert!(masks_overlap((0, 0), &full, (7, 7), &full));
+        assert!(!masks_overlap((0, 0), &full, (8, 0), &full));
+        assert!(!masks_overlap((0, 0), &full, (0, -8), &full));
+    }
+
+    #[test]
+    fn masks_with_negative_offsets() {
+        // Only the bottom right pixel of A and the top left pixel of B.
+        let corner_a = mask(|x, y| x == 7 && y == 7);
+        let corner_b = mask(|x, y| x == 0 && y == 0);
+
+        assert!(masks_overlap((-10, -20), &corner_a, (-3, -13), &corner_b));
+        assert!(!masks_overlap((-10, -20), &corner_a, (-4, -13), &corner_b));
+
+        // B up and to the left of A.
+        assert!(masks_overlap((-3, -13), &corner_b, (-10, -20), &corner_a));
+    }
+
+    #[test]
+    fn masks_overlapping_only_on_transparent_pixels() {
+        let left = mask(|x, _| x < 4);
+        let right = mask(|x, _| x >= 4);
+
+        // The boxes overlap completely, but the opaque halves don't.
+        assert!(boxes_overlap((0, 0), (0, 0)));
+        assert!(!masks_overlap((0, 0), &left, (0, 0), &right));
+
+        // Moving B left by one lines its opaque half up with A's.
+        assert!(masks_overlap((0, 0), &left, (-1, 0), &right));
+    }
+}
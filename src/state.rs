@@ -0,0 +1,375 @@
+use crate::{
+    color::Colour,
+    cpu::{Cpu, Mem, LAYERS},
+    render::{Rect, RenderBackend},
+    sprite::MAP_SIZE,
+    RES,
+};
+
+use std::{
+    fs::{read, write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"ATCS";
+const VERSION: u8 = 1;
+
+/// Reads values back in the order they were written by `save_state`.
+pub struct Reader<'a>(&'a [u8], usize);
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader(data, 0)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        // Lengths come from the file, so they can be anything.
+        let out = self
+            .1
+            .checked_add(len)
+            .and_then(|end| self.0.get(self.1..end))
+            .ok_or_else(|| format!("Data is truncated at byte {}", self.1))?;
+
+        self.1 += len;
+
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn colour(&mut self) -> Result<Colour, String> {
+        let hex = self.u8()?;
+
+        Colour::try_from_hex(hex).ok_or_else(|| format!("Unknown colour hexcode {hex:0>2x} in save state"))
+    }
+}
+
+pub fn write_mem(out: &mut Vec<u8>, mem: &Mem) {
+    match mem {
+        Mem::Str(chars) => {
+            out.push(0xab);
+            for c in chars {
+                out.extend((*c as u32).to_le_bytes());
+            }
+        }
+        Mem::ByteArr(arr) => {
+            out.push(0x8a);
+            out.extend(arr);
+        }
+        Mem::Int(int) => {
+            out.push(0xe0);
+            out.extend(int.to_le_bytes());
+        }
+        Mem::Float(float) => {
+            out.push(0xf0);
+            out.extend(float.to_le_bytes());
+        }
+        Mem::Nil => out.push(0x00),
+    }
+}
+
+pub fn read_mem(reader: &mut Reader) -> Result<Mem, String> {
+    Ok(match reader.u8()? {
+        0xab => {
+            let mut chars = ['\0'; 8];
+
+            for c in chars.iter_mut() {
+                let code = u32::from_le_bytes(reader.bytes(4)?.try_into().unwrap());
+                *c = char::from_u32(code).ok_or_else(|| format!("Invalid character {code:x} at byte {}", reader.1 - 4))?;
+            }
+
+            Mem::Str(chars)
+        }
+        0x8a => Mem::ByteArr(reader.bytes(8)?.try_into().unwrap()),
+        0xe0 => Mem::Int(reader.u64()? as i64),
+        0xf0 => Mem::Float(reader.f64()?),
+        0x00 => Mem::Nil,
+        any => return Err(format!("Unknown type {any:0>2x} at byte {}", reader.1 - 1)),
+    })
+}
+
+impl<T: RenderBackend> Cpu<T> {
+    /// Writes a snapshot of the whole machine to `path`. `code_hash` is the
+    /// `storage::hash` of the running program, which is checked on load, and
+    /// `pc` is the byte execution resumes from.
+    pub fn save_state(&self, path: &Path, code_hash: u64, pc: usize) -> std::io::Result<()> {
+        let mut out = Vec::new();
+
+        out.extend(MAGIC);
+        out.push(VERSION);
+
+        out.extend((self.header.title.len() as u64).to_le_bytes());
+        out.extend(self.header.title.as_bytes());
+        out.extend(code_hash.to_le_bytes());
+        out.extend((pc as u64).to_le_bytes());
+
+        out.push(self.header.repeat as u8);
+        out.push(self.header.alt_colours as u8);
+        out.push(self.header.keep_open as u8);
+        out.push(self.header.debug as u8);
+
+        for mem in &self.memory {
+            write_mem(&mut out, mem);
+        }
+
+        out.push(self.layer as u8);
+        for layer in &self.layers {
+            out.extend(layer.iter().map(|clr| clr.to_hex()));
+        }
+
+        for row in &self.tilemap.tiles {
+            for tile in row {
+                // 0xff can't be used as a tile ID, so it marks empty tiles.
+                out.push(tile.unwrap_or(0xff));
+            }
+        }
+        out.extend((self.tilemap.scroll_x as u64).to_le_bytes());
+        out.extend((self.tilemap.scroll_y as u64).to_le_bytes());
+
+        for channel in &self.synth.channels {
+            out.extend(channel.freq.to_le_bytes());
+            out.push(channel.volume);
+            out.extend((channel.frames as u64).to_le_bytes());
+            out.extend(channel.phase.to_le_bytes());
+        }
+        out.extend((self.synth.lfsr as u64).to_le_bytes());
+
+        match self.music.playing {
+            Some((pattern, row, frames)) => {
+                out.extend([1, pattern, frames]);
+                out.extend((row as u64).to_le_bytes());
+            }
+            None => out.extend([0; 11]),
+        }
+
+        write(path, out)
+    }
+
+    /// Restores a snapshot written by `save_state`, returning the byte to
+    /// resume execution from. The machine is left untouched if the snapshot
+    /// can't be read, refers to tiles or music the cartridge doesn't have,
+    /// or was made with a different program.
+    pub fn load_state(&mut self, path: &Path, code_hash: u64) -> Result<usize, String> {
+        let data = read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let mut reader = Reader::new(&data);
+
+        if reader.bytes(4)? != MAGIC {
+            return Err(format!("{} is not a save state", path.display()));
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported save state version {version} (expected {VERSION})"));
+        }
+
+        let title_len = reader.u64()? as usize;
+        let title = String::from_utf8_lossy(reader.bytes(title_len)?).into_owned();
+
+        if title != self.header.title || reader.u64()? != code_hash {
+            return Err(format!("Save state was made with a different cartridge ({title:?})"));
+        }
+
+        let pc = reader.u64()? as usize;
+
+        let repeat = reader.bool()?;
+        let alt_colours = reader.bool()?;
+        let keep_open = reader.bool()?;
+        let debug = reader.bool()?;
+
+        let mut memory = [Mem::Nil; 255];
+        for mem in memory.iter_mut() {
+            *mem = read_mem(&mut reader)?;
+        }
+
+        let layer = reader.u8()? as usize;
+        if layer >= LAYERS {
+            return Err(format!("Layer {layer} out of bounds in save state"));
+        }
+
+        let mut layers = vec![[Colour::Transparent; RES]; LAYERS];
+        for layer in layers.iter_mut() {
+            for pix in layer.iter_mut() {
+                *pix = reader.colour()?;
+            }
+        }
+
+        let mut tiles = [[None; MAP_SIZE]; MAP_SIZE];
+        for row in tiles.iter_mut() {
+            for tile in row.iter_mut() {
+                *tile = match reader.u8()? {
+                    0xff => None,
+                    id if (id as usize) < self.sprites.len() => Some(id),
+                    id => return Err(format!("Tile {id:0>2x} in save state is not in the sprite sheet")),
+                };
+            }
+        }
+        // Scrolling is kept within the map, like the scroll instructions do.
+        let scroll_x = (reader.u64()? % (MAP_SIZE * 8) as u64) as usize;
+        let scroll_y = (reader.u64()? % (MAP_SIZE * 8) as u64) as usize;
+
+        let mut channels = self.synth.channels;
+        for channel in channels.iter_mut() {
+            channel.freq = reader.f64()?;
+            channel.volume = reader.u8()?;
+            channel.frames = reader.u64()? as u32;
+            channel.phase = reader.f64()?;
+        }
+        let lfsr = reader.u64()? as u16;
+
+        let playing = match reader.bytes(3)? {
+            [1, pattern, frames] => Some((*pattern, reader.u64()? as usize, *frames)),
+            _ => {
+                reader.u64()?;
+                None
+            }
+        };
+
+        if let Some((id, row, _)) = playing {
+            let Some(pattern) = self.music.patterns.get(&id) else {
+                return Err(format!("Pattern {id:0>2x} in save state is not in the cartridge"));
+            };
+
+            if row >= pattern.rows.len() {
+                return Err(format!("Row {row} of pattern {id:0>2x} in save state is out of bounds"));
+            }
+        }
+
+        // Only apply the snapshot once all of it has been read successfully.
+        self.header.repeat = repeat;
+        self.header.alt_colours = alt_colours;
+        self.header.keep_open = keep_open;
+        self.header.debug = debug;
+        self.memory = memory;
+        self.layer = layer;
+        self.layers = layers;
+        self.tilemap.tiles = tiles;
+        self.tilemap.scroll_x = scroll_x;
+        self.tilemap.scroll_y = scroll_y;
+        self.synth.channels = channels;
+        self.synth.lfsr = lfsr;
+        self.music.playing = playing;
+
+        // PEEKPIX reads the composited frame, which would otherwise be stale
+        // until the next flush.
+        self.compose(Rect::FULL);
+        self.dirty = Some(Rect::FULL);
+
+        Ok(pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::{Pattern, CHANNELS},
+        render::Headless,
+    };
+
+    use std::{env, fs, process};
+
+    /// Gives the machine a four row music pattern `02`, as if its cartridge
+    /// had one.
+    fn add_pattern(cpu: &mut Cpu<Headless>) {
+        cpu.music.patterns.insert(
+            2,
+            Pattern {
+                speed: 1,
+                volume: 0xff,
+                rows: vec![[0; CHANNELS]; 4],
+            },
+        );
+    }
+
+    #[test]
+    fn save_then_load() {
+        let path = env::temp_dir().join(format!("atc_console-{}-round-trip.state", process::id()));
+
+        let mut cpu = Cpu::new(Headless::new(0));
+        add_pattern(&mut cpu);
+        cpu.memory[0] = Mem::Int(-3);
+        cpu.memory[1] = Mem::Float(1.5);
+        cpu.memory[2] = Mem::Str(['h', 'i', '\0', '\0', '\0', '\0', '\0', '\0']);
+        cpu.memory[3] = Mem::ByteArr([1, 2, 3, 4, 5, 6, 7, 8]);
+        cpu.layer = 1;
+        cpu.layers[1][5] = Colour::Red;
+        cpu.tilemap.scroll_x = 7;
+        cpu.music.playing = Some((2, 3, 4));
+
+        cpu.save_state(&path, 0x1234, 42).unwrap();
+
+        let mut loaded = Cpu::new(Headless::new(0));
+        add_pattern(&mut loaded);
+        let other = loaded.load_state(&path, 0x4321);
+        let pc = loaded.load_state(&path, 0x1234);
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(other.unwrap_err().contains("different cartridge"));
+        assert_eq!(pc, Ok(42));
+        assert_eq!(loaded.memory, cpu.memory);
+        assert_eq!(loaded.layer, 1);
+        assert!(loaded.layers == cpu.layers);
+        assert_eq!(loaded.tilemap.scroll_x, 7);
+        assert_eq!(loaded.music.playing, Some((2, 3, 4)));
+
+        // The composited frame is rebuilt straight away, rather than on the
+        // next flush.
+        assert_eq!(loaded.buf[5], Colour::Red);
+        assert_eq!(loaded.buf[6], Colour::Green);
+    }
+
+    #[test]
+    fn damaged_state() {
+        let path = env::temp_dir().join(format!("atc_console-{}-damaged.state", process::id()));
+
+        // A title far longer than the file.
+        let data = [&MAGIC[..], &[VERSION], &u64::MAX.to_le_bytes()].concat();
+        fs::write(&path, data).unwrap();
+
+        let mut cpu = Cpu::new(Headless::new(0));
+        add_pattern(&mut cpu);
+
+        assert_eq!(cpu.load_state(&path, 0), Err("Data is truncated at byte 13".to_string()));
+
+        // Saves the machine and loads it straight back, returning where the
+        // background ended up scrolled to.
+        let reload = |cpu: &mut Cpu<Headless>| {
+            cpu.save_state(&path, 0, 0).unwrap();
+            cpu.load_state(&path, 0)?;
+
+            Ok((cpu.tilemap.scroll_x, cpu.tilemap.scroll_y))
+        };
+
+        cpu.tilemap.tiles[3][4] = Some(5);
+        assert_eq!(reload(&mut cpu), Err("Tile 05 in save state is not in the sprite sheet".to_string()));
+        cpu.tilemap.tiles[3][4] = None;
+
+        cpu.music.playing = Some((9, 0, 0));
+        assert_eq!(reload(&mut cpu), Err("Pattern 09 in save state is not in the cartridge".to_string()));
+
+        cpu.music.playing = Some((2, 4, 0));
+        assert_eq!(reload(&mut cpu), Err("Row 4 of pattern 02 in save state is out of bounds".to_string()));
+        cpu.music.playing = None;
+
+        cpu.tilemap.scroll_x = usize::MAX;
+        cpu.tilemap.scroll_y = MAP_SIZE * 8 + 3;
+        assert_eq!(reload(&mut cpu), Ok((usize::MAX % (MAP_SIZE * 8), 3)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
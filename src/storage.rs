@@ -0,0 +1,189 @@
+use crate::{
+    cpu::{Mem, DEFAULT_TITLE},
+    state::{read_mem, write_mem, Reader},
+};
+
+use std::{
+    fs::{read, write},
+    path::PathBuf,
+};
+
+/// Number of persistent slots each cartridge gets.
+pub const SLOTS: usize = 16;
+
+const MAGIC: &[u8; 4] = b"ATCP";
+const VERSION: u8 = 1;
+
+/// Slots that outlive the process, written to a file whenever they change.
+pub struct Storage {
+    path: Option<PathBuf>,
+    slots: [Mem; SLOTS],
+    /// Number of slots the cartridge may use.
+    len: usize,
+}
+
+impl Default for Storage {
+    /// Storage that is never written to disk.
+    fn default() -> Self {
+        Storage {
+            path: None,
+            slots: [Mem::Nil; SLOTS],
+            len: SLOTS,
+        }
+    }
+}
+
+impl Storage {
+    /// Opens the storage file at `path`, starting with empty slots if it
+    /// doesn't exist yet or can't be read. Only the first `len` slots may be
+    /// used.
+    pub fn open(path: PathBuf, len: usize) -> Self {
+        let slots = match read(&path) {
+            Ok(data) => Self::parse(&data).unwrap_or_else(|e| {
+                eprintln!("Failed to read storage from {}: {e}. Starting with empty slots", path.display());
+                [Mem::Nil; SLOTS]
+            }),
+            Err(_) => [Mem::Nil; SLOTS],
+        };
+
+        Storage {
+            path: Some(path),
+            slots,
+            len,
+        }
+    }
+
+    fn parse(data: &[u8]) -> Result<[Mem; SLOTS], String> {
+        let mut reader = Reader::new(data);
+        let mut slots = [Mem::Nil; SLOTS];
+
+        let header = reader.bytes(5).ok();
+        if header != Some(&[MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION]) {
+            return Err(format!("not a version {VERSION} storage file"));
+        }
+
+        for slot in slots.iter_mut() {
+            *slot = read_mem(&mut reader)?;
+        }
+
+        Ok(slots)
+    }
+
+    fn slot(&self, slot: u8) -> usize {
+        if slot as usize >= self.len {
+            panic!("Storage slot {slot} out of bounds ({} slots). (Zero based indexing!)", self.len);
+        }
+
+        slot as usize
+    }
+
+    pub fn load(&self, slot: u8) -> Mem {
+        self.slots[self.slot(slot)]
+    }
+
+    pub fn store(&mut self, slot: u8, mem: Mem) {
+        let slot = self.slot(slot);
+        self.slots[slot] = mem;
+
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let mut out = Vec::new();
+
+        out.extend(MAGIC);
+        out.push(VERSION);
+
+        for mem in &self.slots {
+            write_mem(&mut out, mem);
+        }
+
+        if let Err(e) = write(path, out) {
+            eprintln!("Failed to write storage to {}: {e}", path.display());
+        }
+    }
+}
+
+/// FNV-1a hash of a program's code, which identifies it when it has no title.
+pub fn hash(code: &[u8]) -> u64 {
+    code.iter()
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Name of the storage file for a cartridge. Titled cartridges are keyed by
+/// their title (or storage key), so that updates to a game keep its saves.
+/// Untitled ones are keyed by a hash of their code.
+pub fn file_name(title: &str, code: &[u8]) -> String {
+    if title.is_empty() || title == DEFAULT_TITLE {
+        return format!("{:0>16x}.atcsave", hash(code));
+    }
+
+    let title: String = title
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    format!("{title}.atcsave")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("atc_console-{}-{name}.atcsave", process::id()))
+    }
+
+    #[test]
+    fn store_then_open() {
+        let path = temp_path("round-trip");
+
+        let mut storage = Storage::open(path.clone(), SLOTS);
+        storage.store(0, Mem::Int(1234));
+        storage.store(15, Mem::Str(['h', 'i', '\0', '\0', '\0', '\0', '\0', '\0']));
+
+        let reopened = Storage::open(path.clone(), SLOTS);
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(reopened.load(0), Mem::Int(1234));
+        assert_eq!(reopened.load(1), Mem::Nil);
+        assert_eq!(reopened.load(15), storage.load(15));
+    }
+
+    #[test]
+    #[should_panic(expected = "Storage slot 16 out of bounds (16 slots)")]
+    fn slot_limit() {
+        Storage::default().load(16);
+    }
+
+    #[test]
+    #[should_panic(expected = "Storage slot 4 out of bounds (4 slots)")]
+    fn cartridge_slot_limit() {
+        let mut storage = Storage {
+            len: 4,
+            ..Storage::default()
+        };
+
+        storage.store(4, Mem::Int(1));
+    }
+
+    #[test]
+    fn damaged_file() {
+        assert_eq!(Storage::parse(b"ATCS\x01").unwrap_err(), "not a version 1 storage file");
+        assert_eq!(Storage::parse(b"ATCP\x01\xe0\x01").unwrap_err(), "Data is truncated at byte 6");
+        assert_eq!(Storage::parse(b"ATCP\x01\x77").unwrap_err(), "Unknown type 77 at byte 5");
+
+        // Damaged files are warned about, and start out empty.
+        let path = temp_path("damaged");
+        fs::write(&path, b"ATCP\x01\x77").unwrap();
+
+        let storage = Storage::open(path.clone(), SLOTS);
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(storage.load(0), Mem::Nil);
+    }
+}
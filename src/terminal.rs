@@ -0,0 +1,499 @@
+use crate::{
+    color::Colour,
+    key::Key,
+    render::{Hotkey, Rect, RenderBackend},
+    HEIGHT, RES, WIDTH,
+};
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::File,
+    io::{stdin, Read, Write},
+    process::Command,
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Terminals only report key presses, so a key counts as held for this
+/// long after it was last pressed (or repeated by the terminal).
+const HOLD: Duration = Duration::from_millis(200);
+
+/// How often to check whether the terminal has been resized.
+const RESIZE_CHECK: Duration = Duration::from_secs(1);
+
+/// Read from stdin by the input thread.
+#[derive(Debug, PartialEq)]
+enum Event {
+    /// Keys pressed together, and the character they type, if any.
+    Press(Vec<Key>, Option<char>),
+    Hotkey(Hotkey),
+    /// Ctrl-C, which closes the console.
+    Quit,
+}
+
+/// Runs `stty` on the controlling terminal, returning its output.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty").ok()?)
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Rows and columns of the terminal.
+fn size() -> (usize, usize) {
+    let size = stty(&["size"]).unwrap_or_default();
+
+    match size.split_once(' ').map(|(rows, cols)| (rows.parse(), cols.parse())) {
+        Some((Ok(rows), Ok(cols))) => (rows, cols),
+        _ => (24, 80),
+    }
+}
+
+fn press(keys: &[Key], c: Option<char>) -> Event {
+    Event::Press(keys.to_vec(), c)
+}
+
+/// Splits what was read from stdin into key presses, returning how many
+/// bytes were used. An escape sequence or character that is cut off by the
+/// end of `bytes` is left for the next read to complete.
+fn parse(bytes: &[u8], events: &mut Vec<Event>) -> usize {
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+
+        let event = match byte {
+            0x03 => Event::Quit,
+            // Escape sequences are `ESC [` or `ESC O`, then parameters,
+            // then a final byte in `0x40..=0x7e`.
+            0x1b if matches!(bytes.get(i), Some(b'[' | b'O')) => {
+                let start = i + 1;
+                let Some(len) = bytes[start..].iter().position(|b| (0x40..=0x7e).contains(b)) else {
+                    return i - 1;
+                };
+                i = start + len + 1;
+
+                let params = &bytes[start..start + len];
+                // Modifiers come after a `;`, e.g. `ESC [1;2A` for shift+up.
+                let shift = params.ends_with(b";2");
+
+                let key = match (bytes[start + len], params) {
+                    (b'A', _) => Key::Up,
+                    (b'B', _) => Key::Dwn,
+                    (b'C', _) => Key::Rght,
+                    (b'D', _) => Key::Lft,
+                    (b'~', b"15") => {
+                        events.push(Event::Hotkey(Hotkey::SaveState));
+                        continue;
+                    }
+                    (b'~', b"20") => {
+                        events.push(Event::Hotkey(Hotkey::LoadState));
+                        continue;
+                    }
+                    (b'~', b"24") => {
+                        events.push(Event::Hotkey(Hotkey::Screenshot));
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                if shift {
+                    press(&[Key::LShift, key], None)
+                } else {
+                    press(&[key], None)
+                }
+            }
+            0x1b => press(&[Key::Esc], None),
+            b'\r' | b'\n' => press(&[Key::Enter], Some('\r')),
+            b'\t' => press(&[Key::Tab], Some('\t')),
+            0x7f | 0x08 => press(&[Key::Bksp], Some('\u{8}')),
+            b' ' => press(&[Key::Spc], Some(' ')),
+            // Ctrl-A to Ctrl-Z, apart from the ones handled above.
+            0x01..=0x1a => match Key::from_str(&((byte - 1 + b'A') as char).to_string()) {
+                Some(key) => press(&[Key::LCtrl, key], None),
+                None => continue,
+            },
+            0x80..=0xff => {
+                // Multi-byte UTF-8 characters can only be typed.
+                let rest = &bytes[i - 1..bytes.len().min(i + 3)];
+                let valid = match std::str::from_utf8(rest) {
+                    Ok(valid) => valid,
+                    Err(e) if e.valid_up_to() > 0 => std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap(),
+                    Err(e) if e.error_len().is_none() => return i - 1,
+                    Err(_) => continue,
+                };
+                let c = valid.chars().next().unwrap();
+                i += c.len_utf8() - 1;
+
+                press(&[], Some(c))
+            }
+            _ => {
+                let c = byte as char;
+
+                match Key::from_str(&c.to_string()) {
+                    Some(key) if c.is_ascii_uppercase() => press(&[Key::LShift, key], Some(c)),
+                    Some(key) => press(&[key], Some(c)),
+                    None => press(&[], Some(c)),
+                }
+            }
+        };
+
+        events.push(event);
+    }
+
+    bytes.len()
+}
+
+/// Input read from the terminal so far.
+struct State {
+    /// When each key was last pressed, by keycode.
+    pressed: [Option<Instant>; 256],
+    typed: VecDeque<char>,
+    hotkey: Option<Hotkey>,
+    quit: bool,
+}
+
+/// Draws frames to the terminal with 24-bit colour, two pixels to a
+/// character using `▀`, scaled down to fit. Keys are read from stdin in
+/// raw mode.
+pub struct Terminal {
+    events: Receiver<Event>,
+    state: RefCell<State>,
+    /// The terminal itself, rather than stdout, so that debug output can be
+    /// redirected elsewhere.
+    tty: File,
+    /// Terminal settings to restore once done.
+    saved: String,
+    /// Console pixels per character, in both directions.
+    scale: usize,
+    size: (usize, usize),
+    checked: Instant,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        let saved = stty(&["-g"]).expect("Failed to read the terminal's settings, is the console running in a terminal?");
+        stty(&["raw", "-echo"]);
+
+        let (send, events) = channel();
+
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            let mut events = Vec::new();
+            // Bytes of a key press that hasn't been read in full yet.
+            let mut pending = Vec::new();
+
+            while let Ok(len) = stdin().read(&mut buf) && len > 0 {
+                pending.extend_from_slice(&buf[..len]);
+
+                let used = parse(&pending, &mut events);
+                pending.drain(..used);
+
+                for event in events.drain(..) {
+                    if send.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        let mut tty = File::options()
+            .write(true)
+            .open("/dev/tty")
+            .expect("Failed to open the terminal");
+
+        // Hide the cursor and clear the screen.
+        write!(tty, "\x1b[?25l\x1b[2J").unwrap();
+
+        let size = size();
+
+        Terminal {
+            events,
+            state: RefCell::new(State {
+                pressed: [None; 256],
+                typed: VecDeque::new(),
+                hotkey: None,
+                quit: false,
+            }),
+            tty,
+            saved,
+            scale: Self::scale(size),
+            size,
+            checked: Instant::now(),
+        }
+    }
+
+    /// Smallest scale at which the whole screen fits, leaving a line free
+    /// at the bottom.
+    fn scale((rows, cols): (usize, usize)) -> usize {
+        let rows = rows.saturating_sub(1).max(1) * 2;
+        let cols = cols.max(1);
+
+        ((WIDTH + cols - 1) / cols).max((HEIGHT + rows - 1) / rows).max(1)
+    }
+
+    /// Applies any key presses read since the last call.
+    fn poll(&self) {
+        let mut state = self.state.borrow_mut();
+
+        loop {
+            match self.events.try_recv() {
+                Ok(Event::Press(keys, c)) => {
+                    for key in keys {
+                        state.pressed[key as usize] = Some(Instant::now());
+                    }
+
+                    state.typed.extend(c);
+                }
+                Ok(Event::Hotkey(hotkey)) => state.hotkey = Some(hotkey),
+                Ok(Event::Quit) | Err(TryRecvError::Disconnected) => {
+                    state.quit = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+    }
+
+    /// Average colour of a `scale` by `scale` block of pixels.
+    fn sample(&self, buf: &[Colour; RES], x: usize, y: usize) -> [u8; 3] {
+        let mut sum = [0; 3];
+        let mut count = 0;
+
+        for y in y..(y + self.scale).min(HEIGHT) {
+            for x in x..(x + self.scale).min(WIDTH) {
+                let [r, g, b, _] = buf[x + y * WIDTH].into_rgba();
+
+                sum[0] += r as usize;
+                sum[1] += g as usize;
+                sum[2] += b as usize;
+                count += 1;
+            }
+        }
+
+        sum.map(|total| (total / count) as u8)
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for Terminal {
+    fn update(&mut self, buf: &[Colour; RES], dirty: Option<Rect>) {
+        let mut dirty = dirty;
+
+        if self.checked.elapsed() > RESIZE_CHECK {
+            self.checked = Instant::now();
+
+            let size = size();
+
+            if size != self.size {
+                self.size = size;
+                self.scale = Self::scale(size);
+                dirty = Some(Rect::FULL);
+                write!(self.tty, "\x1b[0m\x1b[2J").unwrap();
+            }
+        }
+
+        let Some(dirty) = dirty else {
+            return;
+        };
+
+        // Each line of text shows two rows of blocks.
+        let line_height = self.scale * 2;
+        let mut out = String::new();
+
+        for line in dirty.y0 / line_height..(dirty.y1 + line_height - 1) / line_height {
+            let mut colours = None;
+
+            write!(out, "\x1b[{};1H", line + 1).unwrap();
+
+            for x in (0..WIDTH).step_by(self.scale) {
+                let top_y = line * line_height;
+                let bottom_y = top_y + self.scale;
+
+                let top = self.sample(buf, x, top_y);
+
+                let next = if bottom_y < HEIGHT {
+                    (top, Some(self.sample(buf, x, bottom_y)))
+                } else {
+                    (top, None)
+                };
+
+                if colours != Some(next) {
+                    let [r, g, b] = top;
+                    write!(out, "\x1b[0m\x1b[38;2;{r};{g};{b}m").unwrap();
+
+                    if let Some([r, g, b]) = next.1 {
+                        write!(out, "\x1b[48;2;{r};{g};{b}m").unwrap();
+                    }
+
+                    colours = Some(next);
+                }
+
+                out.push('▀');
+            }
+
+            out.push_str("\x1b[0m");
+        }
+
+        self.tty.write_all(out.as_bytes()).unwrap();
+    }
+
+    fn is_open(&self) -> bool {
+        self.poll();
+
+        !self.state.borrow().quit
+    }
+
+    fn set_title(&mut self, title: &str) {
+        write!(self.tty, "\x1b]0;{title}\x07").unwrap();
+    }
+
+    fn key(&self, key: Key) -> bool {
+        self.state.borrow().pressed[key as usize].map_or(false, |time| time.elapsed() < HOLD)
+    }
+
+    fn typed(&mut self) -> Option<char> {
+        self.poll();
+
+        self.state.borrow_mut().typed.pop_front()
+    }
+
+    fn hotkey(&self) -> Option<Hotkey> {
+        self.state.borrow_mut().hotkey.take()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Leave the last frame on screen, with the cursor below it.
+        let lines = (HEIGHT + self.scale * 2 - 1) / (self.scale * 2);
+        let _ = write!(self.tty, "\x1b[0m\x1b[{};1H\x1b[?25h", lines + 1);
+
+        stty(&[&self.saved]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events parsed from `bytes`, and how many bytes were used.
+    fn events(bytes: &[u8]) -> (Vec<Event>, usize) {
+        let mut events = Vec::new();
+        let used = parse(bytes, &mut events);
+
+        (events, used)
+    }
+
+    #[test]
+    fn arrows_with_shift() {
+        let (events, _) = events(b"\x1b[A\x1b[1;2B\x1bOC\x1b[1;5D");
+
+        assert_eq!(
+            events,
+            [
+                press(&[Key::Up], None),
+                press(&[Key::LShift, Key::Dwn], None),
+                press(&[Key::Rght], None),
+                // Only shift is understood as a modifier.
+                press(&[Key::Lft], None),
+            ]
+        );
+    }
+
+    #[test]
+    fn function_key_hotkeys() {
+        // F5, Delete (which isn't used), F9 and F12.
+        let (events, used) = events(b"\x1b[15~\x1b[3~\x1b[20~\x1b[24~");
+
+        assert_eq!(
+            events,
+            [
+                Event::Hotkey(Hotkey::SaveState),
+                Event::Hotkey(Hotkey::LoadState),
+                Event::Hotkey(Hotkey::Screenshot),
+            ]
+        );
+        assert_eq!(used, 19);
+    }
+
+    #[test]
+    fn control_keys() {
+        let (events, _) = events(b"\x01\x18\x03\r\x7f\x1b");
+
+        assert_eq!(
+            events,
+            [
+                press(&[Key::LCtrl, Key::A], None),
+                press(&[Key::LCtrl, Key::X], None),
+                Event::Quit,
+                press(&[Key::Enter], Some('\r')),
+                press(&[Key::Bksp], Some('\u{8}')),
+                // A lone escape is the Escape key.
+                press(&[Key::Esc], None),
+            ]
+        );
+    }
+
+    #[test]
+    fn uppercase_is_shift() {
+        let (events, _) = events(b"aA!");
+
+        assert_eq!(
+            events,
+            [
+                press(&[Key::A], Some('a')),
+                press(&[Key::LShift, Key::A], Some('A')),
+                press(&[], Some('!')),
+            ]
+        );
+    }
+
+    #[test]
+    fn utf8_split_across_reads() {
+        // The first half of an `é`.
+        let (first, used) = events(b"x\xc3");
+
+        assert_eq!(first, [press(&[Key::X], Some('x'))]);
+        assert_eq!(used, 1);
+
+        let (second, used) = events("é€".as_bytes());
+
+        assert_eq!(second, [press(&[], Some('é')), press(&[], Some('€'))]);
+        assert_eq!(used, 5);
+
+        // Bytes that can never be UTF-8 are skipped.
+        let (invalid, used) = events(b"\xff\xc3(");
+
+        assert_eq!(invalid, [press(&[], Some('('))]);
+        assert_eq!(used, 3);
+    }
+
+    #[test]
+    fn escape_sequence_split_across_reads() {
+        let (first, used) = events(b"a\x1b[1;2");
+
+        assert_eq!(first, [press(&[Key::A], Some('a'))]);
+        assert_eq!(used, 1);
+
+        let (second, used) = events(b"\x1b[1;2A");
+
+        assert_eq!(second, [press(&[Key::LShift, Key::Up], None)]);
+        assert_eq!(used, 6);
+    }
+}